@@ -0,0 +1,714 @@
+use crate::message::{self, MessageFormat};
+use crate::{
+    cache_name, check_crates, crate_path, download, download_source, index, vendor, Options,
+};
+use anyhow::Context as _;
+use cargo::core::dependency::DepKind;
+use cargo::core::resolver::Resolve;
+use cargo::core::{PackageSet, SourceId, Workspace};
+use cargo::sources::SourceConfigMap;
+use cargo::util::errors::*;
+use cargo::util::{human_readable_bytes, GlobalContext};
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, prelude::*, IsTerminal};
+use std::path::{Path, PathBuf};
+
+#[derive(clap::Args, Clone)]
+pub struct AddOptions {
+    /// Path to the local registry, defaults to the configured one
+    #[arg(default_value_t, hide_default_value = true)]
+    pub path: String,
+    /// Crates to add, as NAME or NAME@VERSION_REQ
+    pub crates: Vec<String>,
+    /// Also add the crates listed in FILE, one NAME or NAME@VERSION_REQ per
+    /// line, with `#` starting a comment
+    #[arg(long, value_name = "FILE")]
+    pub from_file: Option<String>,
+    /// Features to enable, as FEATURE or CRATE/FEATURE when adding several
+    /// crates
+    #[arg(short = 'F', long, value_delimiter = ',')]
+    pub features: Vec<String>,
+    /// Don't enable the default features of the added crates
+    #[arg(long, default_value_t = false)]
+    pub no_default_features: bool,
+    /// Also add the dev-dependencies of the requested crates, so they can be
+    /// tested offline
+    #[arg(long, default_value_t = false)]
+    pub dev: bool,
+    /// Take the crates from this git repository instead of crates.io
+    #[arg(long, value_name = "URL")]
+    pub git: Option<String>,
+    /// Commit, tag or branch of the git repository to check out
+    #[arg(long, requires = "git")]
+    pub rev: Option<String>,
+    /// Only print which crates would be added, without touching the registry
+    #[arg(long, default_value_t = false)]
+    pub dry_run: bool,
+    /// Add yanked versions when they are the only ones matching a requirement
+    #[arg(long, default_value_t = false, conflicts_with = "git")]
+    pub allow_yanked: bool,
+    /// Add the latest version matching each requirement without asking,
+    /// even on a terminal
+    #[arg(long, default_value_t = false, conflicts_with = "all_matching")]
+    pub latest: bool,
+    /// Add every version matching each requirement
+    #[arg(long, default_value_t = false, conflicts_with = "git")]
+    pub all_matching: bool,
+}
+
+/// A version as listed in a registry index, with just what's needed to
+/// tell whether it can be picked.
+#[derive(Deserialize)]
+struct IndexVersion {
+    vers: String,
+    cksum: String,
+    #[serde(default)]
+    yanked: bool,
+}
+
+/// Adds the requested crates along with their dependencies to the registry.
+///
+/// Rather than walking version requirements ourselves, a throwaway package
+/// depending on every requested crate is resolved by Cargo, so the set of
+/// crates added is exactly what a real build of a consumer would pick,
+/// including optional dependencies activated through features. Build
+/// dependencies are part of that resolution, while dev-dependencies only are
+/// with `--dev`. Crates taken from a git repository are packaged the same
+/// way `--sync --git` does.
+///
+/// Cargo never picks a yanked version unless a lock file already has it, so
+/// with `--allow-yanked` requirements only matched by yanked versions are
+/// pinned in a lock file seeded before resolving.
+///
+/// Cargo also picks the latest version matching each requirement, which on a
+/// terminal is only done once asked which of them to add, unless `--latest`
+/// is given. The versions are listed from the index of `registry_id`, the
+/// same registry Cargo resolves against. With `--all-matching`, or when
+/// several versions are picked, each is pinned and the crates are added in
+/// rounds, as Cargo only resolves one semver compatible version of a crate at
+/// a time. Every round is resolved and checked before any is written.
+pub fn add(
+    options: &AddOptions,
+    registry_id: &SourceId,
+    global: &Options,
+    config: &GlobalContext,
+) -> CargoResult<BTreeMap<SourceId, PathBuf>> {
+    let mut specs = options.crates.clone();
+    if let Some(ref file) = options.from_file {
+        specs.extend(read_crate_list(Path::new(file))?);
+    }
+    if specs.is_empty() {
+        anyhow::bail!("no crates to add");
+    }
+    let interactive = io::stdin().is_terminal() && io::stderr().is_terminal();
+    if options.git.is_some() || options.latest || !(options.all_matching || interactive) {
+        return add_specs(&specs, options, registry_id, global, config);
+    }
+
+    // Check the features against everything requested, before it is split
+    // into rounds.
+    synthetic_manifest(&specs, options, registry_id)?;
+    let mut picked = Vec::new();
+    for spec in specs.iter() {
+        let (name, req) = parse_spec(spec)?;
+        let versions = match index_versions(name, registry_id, config) {
+            Ok(versions) => versions,
+            Err(e) if options.all_matching => return Err(e),
+            // Let Cargo pick, and report what's wrong with the crate.
+            Err(_) => Vec::new(),
+        };
+        let mut matching = versions
+            .into_iter()
+            .filter(|v| !v.yanked || options.allow_yanked)
+            .filter_map(|v| semver::Version::parse(&v.vers).ok())
+            .filter(|v| req.matches(v))
+            .collect::<Vec<_>>();
+        matching.sort_by(|a, b| b.cmp(a));
+        if !options.all_matching && matching.len() > 1 {
+            matching = pick_versions(spec, matching)?;
+        }
+        picked.push(match matching.len() {
+            0 => vec![spec.clone()],
+            _ => matching
+                .iter()
+                .map(|v| format!("{}@={}", name, v))
+                .collect(),
+        });
+    }
+
+    let rounds = picked.iter().map(Vec::len).max().unwrap_or(0);
+    let mut resolved = Vec::new();
+    for round in 0..rounds {
+        let specs = picked
+            .iter()
+            .filter_map(|versions| versions.get(round).cloned())
+            .collect::<Vec<_>>();
+        // Features of crates left out of this round are for another one.
+        let names = specs
+            .iter()
+            .map(|spec| parse_spec(spec).map(|(name, _)| name))
+            .collect::<CargoResult<HashSet<_>>>()?;
+        let features = options
+            .features
+            .iter()
+            .filter(|f| match f.trim().split_once('/') {
+                Some((krate, _)) => names.contains(krate),
+                None => true,
+            })
+            .cloned()
+            .collect();
+        let options = AddOptions {
+            features,
+            ..options.clone()
+        };
+        resolved.push(resolve_specs(&specs, &options, registry_id, config)?);
+    }
+
+    let mut sources = BTreeMap::new();
+    if options.dry_run {
+        for (_, _, resolve) in resolved.iter() {
+            dry_run(
+                resolve,
+                Path::new(&options.path),
+                registry_id,
+                global,
+                config,
+            );
+        }
+        return Ok(sources);
+    }
+    for (_, packages, resolve) in resolved.iter() {
+        download(packages, resolve, global, false)?;
+        check_crates(packages, resolve, registry_id, global, false, config)?;
+    }
+    for (_, packages, resolve) in resolved.iter() {
+        sources.extend(vendor(
+            packages,
+            resolve,
+            Path::new(&options.path),
+            registry_id,
+            global,
+            /* git = */ false,
+            /* no_delete = */ true,
+            config,
+        )?);
+    }
+    Ok(sources)
+}
+
+/// Asks which of the `versions` matching `spec`, newest first, to add, the
+/// latest when nothing is picked.
+fn pick_versions(spec: &str, versions: Vec<semver::Version>) -> CargoResult<Vec<semver::Version>> {
+    const SHOWN: usize = 20;
+    eprintln!("{} versions match `{}`:", versions.len(), spec);
+    for (i, version) in versions.iter().take(SHOWN).enumerate() {
+        eprintln!("  {:>2}) {}", i + 1, version);
+    }
+    if versions.len() > SHOWN {
+        eprintln!(
+            "  and {} older ones, type `all` or give a narrower requirement",
+            versions.len() - SHOWN
+        );
+    }
+    let shown = SHOWN.min(versions.len());
+    loop {
+        eprint!("versions to add, e.g. `1,3`, `all`, or nothing for the latest: ");
+        io::stderr().flush()?;
+        let mut line = String::new();
+        if io::stdin().read_line(&mut line)? == 0 {
+            anyhow::bail!("no versions of `{}` picked", spec);
+        }
+        let line = line.trim();
+        if line.is_empty() {
+            return Ok(versions.into_iter().take(1).collect());
+        }
+        if line == "all" {
+            return Ok(versions);
+        }
+        let picked = line
+            .split(',')
+            .map(|n| {
+                n.trim()
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|n| (1..=shown).contains(n))
+            })
+            .collect::<Option<Vec<_>>>();
+        match picked {
+            Some(picked) => {
+                return Ok(picked
+                    .into_iter()
+                    .map(|n| versions[n - 1].clone())
+                    .collect())
+            }
+            None => eprintln!("`{}` isn't a list of the numbers above", line),
+        }
+    }
+}
+
+/// Resolves `specs` together and adds what they resolve to.
+fn add_specs(
+    specs: &[String],
+    options: &AddOptions,
+    registry_id: &SourceId,
+    global: &Options,
+    config: &GlobalContext,
+) -> CargoResult<BTreeMap<SourceId, PathBuf>> {
+    let (_td, packages, resolve) = resolve_specs(specs, options, registry_id, config)?;
+    if options.dry_run {
+        dry_run(
+            &resolve,
+            Path::new(&options.path),
+            registry_id,
+            global,
+            config,
+        );
+        return Ok(BTreeMap::new());
+    }
+
+    // Fetch the whole closure in one batch: cargo downloads it concurrently
+    // (bounded by `http.multiplexing` and friends) with a shared progress
+    // bar, leaving only the local copies and index updates to `vendor`.
+    download(&packages, &resolve, global, options.git.is_some())?;
+
+    vendor(
+        &packages,
+        &resolve,
+        Path::new(&options.path),
+        registry_id,
+        global,
+        options.git.is_some(),
+        /* no_delete = */ true,
+        config,
+    )
+}
+
+/// Resolves `specs` together in a throwaway package, returned along with the
+/// resolution as it must outlive it.
+fn resolve_specs<'gctx>(
+    specs: &[String],
+    options: &AddOptions,
+    registry_id: &SourceId,
+    config: &'gctx GlobalContext,
+) -> CargoResult<(tempfile::TempDir, PackageSet<'gctx>, Resolve)> {
+    let td = tempfile::Builder::new()
+        .prefix("cargo-local-registry-add")
+        .tempdir()?;
+    let manifest = td.path().join("Cargo.toml");
+    fs::create_dir(td.path().join("src"))?;
+    File::create(td.path().join("src/lib.rs"))?;
+    let mut contents = synthetic_manifest(specs, options, registry_id)?;
+    File::create(&manifest).and_then(|mut f| f.write_all(contents.as_bytes()))?;
+    if options.allow_yanked {
+        let lock = seeded_lock(specs, registry_id, config)?;
+        File::create(td.path().join("Cargo.lock"))
+            .and_then(|mut f| f.write_all(lock.as_bytes()))?;
+    }
+
+    let ws = Workspace::new(&manifest, config)?;
+    let (mut packages, mut resolve) = match cargo::ops::resolve_ws(&ws) {
+        Ok(resolved) => resolved,
+        Err(e) if !options.allow_yanked && options.git.is_none() => {
+            // Cargo reports requirements only matched by yanked versions as
+            // if nothing matched, so look for those to explain the failure.
+            for spec in specs.iter() {
+                let (name, req) = parse_spec(spec)?;
+                let yanked = match only_yanked(name, &req, registry_id, config) {
+                    Ok(yanked) if !yanked.is_empty() => yanked,
+                    _ => continue,
+                };
+                let versions = yanked.iter().map(|v| v.vers.as_str()).collect::<Vec<_>>();
+                anyhow::bail!(
+                    "`{}` only matches yanked versions: {} \
+                     (pass `--allow-yanked` to add them anyway)",
+                    spec,
+                    versions.join(", ")
+                );
+            }
+            return Err(e.context("failed to resolve crates to add"));
+        }
+        Err(e) => return Err(e.context("failed to resolve crates to add")),
+    };
+
+    // Cargo never resolves the dev-dependencies of dependencies, so turn
+    // those of the requested crates into regular dependencies and resolve
+    // again, on top of the lock file from the first pass.
+    if options.dev {
+        let root = ws.current()?.package_id();
+        let dev_deps = resolve
+            .deps(root)
+            .map(|(id, _)| packages.get_one(id))
+            .collect::<CargoResult<Vec<_>>>()?
+            .into_iter()
+            .flat_map(|pkg| pkg.dependencies().iter().map(move |dep| (pkg, dep)))
+            .filter(|(_, dep)| dep.kind() == DepKind::Development);
+        for (i, (pkg, dep)) in dev_deps.enumerate() {
+            let registry = if dep.source_id().is_crates_io() {
+                String::new()
+            } else if dep.source_id().is_registry() {
+                format!(", registry-index = \"{}\"", dep.source_id().url())
+            } else {
+                config.shell().warn(format!(
+                    "skipping dev-dependency `{}` of `{}`, it isn't from a registry",
+                    dep.package_name(),
+                    pkg.package_id()
+                ))?;
+                continue;
+            };
+            let features = dep
+                .features()
+                .iter()
+                .map(|f| format!("\"{}\"", f))
+                .collect::<Vec<_>>();
+            contents.push_str(&format!(
+                "dev{} = {{ package = \"{}\", version = \"{}\", features = [{}], \
+                 default-features = {}{} }}\n",
+                i,
+                dep.package_name(),
+                dep.version_req(),
+                features.join(", "),
+                dep.uses_default_features(),
+                registry
+            ));
+        }
+        File::create(&manifest).and_then(|mut f| f.write_all(contents.as_bytes()))?;
+        let ws = Workspace::new(&manifest, config)?;
+        (packages, resolve) = cargo::ops::resolve_ws(&ws)
+            .with_context(|| "failed to resolve dev-dependencies of crates to add")?;
+    }
+    Ok((td, packages, resolve))
+}
+
+/// Prints every crate `vendor` would add, along with its size when Cargo
+/// already has it downloaded (the index doesn't record sizes) and whether the
+/// registry already has it, as a `crate-planned` message for each with
+/// `--message-format json`.
+fn dry_run(
+    resolve: &Resolve,
+    local_dst: &Path,
+    registry_id: &SourceId,
+    options: &Options,
+    config: &GlobalContext,
+) {
+    let mut total = 0;
+    let mut count = 0;
+    for id in resolve.iter() {
+        if !id.source_id().is_registry() && !id.source_id().is_git() {
+            continue;
+        }
+        let source_id = download_source(id, registry_id);
+        let local_dst = if source_id != *registry_id && options.split_sources {
+            local_dst.join(cache_name(&source_id))
+        } else {
+            local_dst.to_path_buf()
+        };
+        let filename = format!("{}-{}.crate", id.name(), id.version());
+        let present = crate_path(&local_dst, &id.name(), &id.version().to_string()).exists();
+        let from = if id.source_id().is_git() {
+            id.source_id()
+        } else {
+            source_id
+        };
+        let mut bytes = None;
+        let status = if present {
+            "already in the registry".to_string()
+        } else if id.source_id().is_git() {
+            count += 1;
+            format!("packaged from {}", id.source_id().url())
+        } else {
+            count += 1;
+            let cached = config
+                .registry_cache_path()
+                .join(cache_name(&source_id))
+                .join(&filename)
+                .into_path_unlocked();
+            match fs::metadata(cached) {
+                Ok(metadata) => {
+                    total += metadata.len();
+                    bytes = Some(metadata.len());
+                    let (size, unit) = human_readable_bytes(metadata.len());
+                    format!("{:.1}{}", size, unit)
+                }
+                Err(_) => "size unknown, not downloaded yet".to_string(),
+            }
+        };
+        match options.message_format {
+            MessageFormat::Human => println!("{} {} ({})", id.name(), id.version(), status),
+            MessageFormat::Json => message::emit(json!({
+                "reason": "crate-planned",
+                "name": id.name().as_str(),
+                "version": id.version().to_string(),
+                "source": from.url().as_str(),
+                "present": present,
+                "bytes": bytes,
+            })),
+        }
+    }
+    if options.message_format == MessageFormat::Json {
+        message::emit(json!({
+            "reason": "dry-run",
+            "crates": count,
+            "bytes": total,
+        }));
+        return;
+    }
+    let (size, unit) = human_readable_bytes(total);
+    println!(
+        "would add {} crates ({:.1}{} already downloaded)",
+        count, size, unit
+    );
+}
+
+/// Builds the manifest of a package depending on each of the requested crates
+/// with the requested features. Every dependency gets its own renamed entry
+/// so the same crate can be requested at several versions.
+fn synthetic_manifest(
+    specs: &[String],
+    options: &AddOptions,
+    registry_id: &SourceId,
+) -> CargoResult<String> {
+    let mut features = BTreeMap::new();
+    for feature in options.features.iter().map(|f| f.trim()) {
+        let (krate, feature) = match feature.split_once('/') {
+            Some((krate, feature)) => (Some(krate), feature),
+            None if specs.len() == 1 => (None, feature),
+            None => anyhow::bail!(
+                "feature `{}` must be given as CRATE/FEATURE when adding several crates",
+                feature
+            ),
+        };
+        if feature.is_empty()
+            || !feature
+                .chars()
+                .all(|c| c.is_alphanumeric() || "-_+.".contains(c))
+        {
+            anyhow::bail!("invalid feature name `{}`", feature);
+        }
+        features
+            .entry(krate)
+            .or_insert_with(Vec::new)
+            .push(format!("\"{}\"", feature));
+    }
+
+    let mut manifest = String::from(
+        "[package]\n\
+         name = \"cargo-local-registry-add\"\n\
+         version = \"0.0.0\"\n\
+         edition = \"2021\"\n\
+         \n\
+         [workspace]\n\
+         \n\
+         [dependencies]\n",
+    );
+    let source = match (&options.git, &options.rev) {
+        (Some(git), Some(rev)) => format!(", git = {}, rev = {}", toml_str(git), toml_str(rev)),
+        (Some(git), None) => format!(", git = {}", toml_str(git)),
+        // Crates come from `--host` when it is given.
+        _ if !registry_id.is_crates_io() => {
+            format!(
+                ", registry-index = {}",
+                toml_str(registry_id.url().as_str())
+            )
+        }
+        _ => String::new(),
+    };
+    let mut names = HashSet::new();
+    for (i, spec) in specs.iter().enumerate() {
+        let (name, req) = parse_spec(spec)?;
+        names.insert(name);
+        let mut enabled = features.get(&Some(name)).cloned().unwrap_or_default();
+        enabled.extend(features.get(&None).cloned().unwrap_or_default());
+        manifest.push_str(&format!(
+            "dep{} = {{ package = \"{}\", version = \"{}\", features = [{}], \
+             default-features = {}{} }}\n",
+            i,
+            name,
+            req,
+            enabled.join(", "),
+            !options.no_default_features,
+            source
+        ));
+    }
+    if let Some(krate) = features.keys().flatten().find(|k| !names.contains(*k)) {
+        anyhow::bail!(
+            "features were given for `{}`, which isn't being added",
+            krate
+        );
+    }
+    Ok(manifest)
+}
+
+/// Quotes a string as a TOML basic string.
+fn toml_str(s: &str) -> String {
+    serde_json::to_string(s).unwrap()
+}
+
+/// Reads a list of crate specs, one per line, ignoring blank lines and
+/// comments.
+fn read_crate_list(path: &Path) -> CargoResult<Vec<String>> {
+    let contents = crate::read(path)?;
+    Ok(contents
+        .lines()
+        .map(|line| line.split('#').next().unwrap().trim())
+        .filter(|line| !line.is_empty())
+        .map(|line| line.to_string())
+        .collect())
+}
+
+/// Splits a `NAME[@VERSION_REQ]` spec, checking both halves so they can be
+/// pasted into a manifest as is.
+pub fn parse_spec(spec: &str) -> CargoResult<(&str, semver::VersionReq)> {
+    let (name, req) = match spec.split_once('@') {
+        Some((name, req)) => (name, req),
+        None => (spec, "*"),
+    };
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        anyhow::bail!("invalid crate name in `{}`", spec);
+    }
+    let req = req
+        .parse::<semver::VersionReq>()
+        .with_context(|| format!("invalid version requirement in `{}`", spec))?;
+    Ok((name, req))
+}
+
+/// Builds a lock file pinning, for each requirement only matched by yanked
+/// versions, the highest of them, which Cargo then keeps while resolving.
+fn seeded_lock(
+    specs: &[String],
+    registry_id: &SourceId,
+    config: &GlobalContext,
+) -> CargoResult<String> {
+    let source = registry_id.as_url().to_string();
+    let mut deps = Vec::new();
+    let mut packages = String::new();
+    for spec in specs {
+        let (name, req) = parse_spec(spec)?;
+        let yanked = match only_yanked(name, &req, registry_id, config) {
+            Ok(yanked) => yanked,
+            Err(e) => {
+                config.shell().warn(format!(
+                    "not pinning the yanked versions of `{}`: {:#}",
+                    name, e
+                ))?;
+                continue;
+            }
+        };
+        let pinned = yanked
+            .into_iter()
+            .max_by_key(|v| semver::Version::parse(&v.vers).ok());
+        if let Some(v) = pinned {
+            deps.push(format!("\"{} {}\"", name, v.vers));
+            packages.push_str(&format!(
+                "\n[[package]]\nname = \"{}\"\nversion = \"{}\"\n\
+                 source = \"{}\"\nchecksum = \"{}\"\n",
+                name, v.vers, source, v.cksum
+            ));
+        }
+    }
+    Ok(format!(
+        "version = 3\n\n[[package]]\nname = \"cargo-local-registry-add\"\n\
+         version = \"0.0.0\"\ndependencies = [{}]\n{}",
+        deps.join(", "),
+        packages
+    ))
+}
+
+/// Returns the versions of `name` matching `req` if they are all yanked, or
+/// nothing when at least one of them isn't.
+fn only_yanked(
+    name: &str,
+    req: &semver::VersionReq,
+    registry_id: &SourceId,
+    config: &GlobalContext,
+) -> CargoResult<Vec<IndexVersion>> {
+    let matching = index_versions(name, registry_id, config)?
+        .into_iter()
+        .filter(|v| semver::Version::parse(&v.vers).is_ok_and(|v| req.matches(&v)))
+        .collect::<Vec<_>>();
+    if matching.iter().any(|v| !v.yanked) {
+        return Ok(Vec::new());
+    }
+    Ok(matching)
+}
+
+/// Fetches every version of a crate, yanked ones included, from the index of
+/// `registry_id`, or of the source Cargo's configuration replaces it with, like
+/// a mirror. Only sparse and local registries can be looked up.
+fn index_versions(
+    name: &str,
+    registry_id: &SourceId,
+    config: &GlobalContext,
+) -> CargoResult<Vec<IndexVersion>> {
+    let source_id = SourceConfigMap::new(config)?
+        .load(*registry_id, &HashSet::new())?
+        .replaced_source_id();
+    let name = name.to_lowercase();
+    let lines = if source_id.is_sparse() {
+        let url = format!(
+            "{}{}/{}",
+            source_id.url().as_str().trim_start_matches("sparse+"),
+            index::prefix(&name),
+            name
+        );
+        fetch(&url, config)?
+    } else if source_id.is_registry() && !source_id.is_remote_registry() {
+        let root = source_id
+            .url()
+            .to_file_path()
+            .map_err(|()| anyhow::format_err!("invalid local registry `{}`", source_id))?;
+        match fs::read_to_string(index::path(&root, &name)) {
+            Ok(lines) => lines,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e.into()),
+        }
+    } else {
+        anyhow::bail!(
+            "can't list the versions of `{}` in `{}`, only sparse and local registries \
+             can be looked up",
+            name,
+            source_id
+        );
+    };
+    lines
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            serde_json::from_str(line)
+                .with_context(|| format!("invalid index entry for `{}`", name))
+        })
+        .collect()
+}
+
+/// Downloads the index file at `url`.
+fn fetch(url: &str, config: &GlobalContext) -> CargoResult<String> {
+    let mut body = Vec::new();
+    let mut handle = cargo::util::network::http::http_handle(config)?;
+    handle.get(true)?;
+    handle.url(url)?;
+    handle.follow_location(true)?;
+    {
+        let mut transfer = handle.transfer();
+        transfer.write_function(|data| {
+            body.extend_from_slice(data);
+            Ok(data.len())
+        })?;
+        transfer
+            .perform()
+            .with_context(|| format!("failed to download `{}`", url))?;
+    }
+    let code = handle.response_code()?;
+    if code == 404 {
+        return Ok(String::new());
+    }
+    if code != 200 {
+        anyhow::bail!("failed to download `{}`, got {}", url, code);
+    }
+    Ok(String::from_utf8(body)?)
+}
@@ -0,0 +1,219 @@
+use crate::message::{self, MessageFormat};
+use crate::{index, read, Error};
+use anyhow::Context as _;
+use cargo::util::errors::*;
+use cargo::util::GlobalContext;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::path::{Path, PathBuf};
+
+const ADVISORY_DB: &str = "https://github.com/rustsec/advisory-db";
+
+#[derive(clap::Args)]
+pub struct AuditOptions {
+    /// Path to the local registry, defaults to the configured one
+    #[arg(default_value_t, hide_default_value = true)]
+    pub path: String,
+    /// Checkout of the RustSec advisory database, defaults to the one
+    /// cargo-audit uses in Cargo's home, cloned or updated as needed
+    #[arg(long, value_name = "PATH")]
+    pub db: Option<String>,
+    /// Print the findings as a JSON array, deprecated in favor of
+    /// `--message-format json`
+    #[arg(long, default_value_t = false, hide = true)]
+    pub json: bool,
+}
+
+/// The TOML front matter of an advisory.
+#[derive(Deserialize)]
+struct Advisory {
+    advisory: AdvisoryInfo,
+    #[serde(default)]
+    versions: Versions,
+}
+
+#[derive(Deserialize)]
+struct AdvisoryInfo {
+    id: String,
+    package: String,
+    informational: Option<String>,
+    withdrawn: Option<toml::Value>,
+}
+
+#[derive(Deserialize, Default)]
+struct Versions {
+    #[serde(default)]
+    patched: Vec<String>,
+    #[serde(default)]
+    unaffected: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct Finding {
+    #[serde(rename = "crate")]
+    name: String,
+    version: String,
+    id: String,
+    title: String,
+    /// `vulnerability`, or the kind of informational advisory, e.g.
+    /// `unmaintained`.
+    kind: String,
+}
+
+/// Checks every crate version in the registry against the advisory
+/// database, failing if any of them is vulnerable. Informational advisories,
+/// like unmaintained crates, are reported without failing.
+pub fn audit(
+    options: &AuditOptions,
+    format: MessageFormat,
+    config: &GlobalContext,
+) -> CargoResult<()> {
+    let db = match options.db {
+        Some(ref db) => PathBuf::from(db),
+        None => {
+            let db = config.home().join("advisory-db").into_path_unlocked();
+            fetch_db(&db, config)?;
+            db
+        }
+    };
+    let advisories = load_advisories(&db.join("crates"))?;
+
+    let mut entries = index::entries(Path::new(&options.path))?;
+    entries.sort_by(|a, b| (&a.name, &a.vers).cmp(&(&b.name, &b.vers)));
+    let mut findings = Vec::new();
+    for pkg in entries.iter() {
+        let version = match semver::Version::parse(&pkg.vers) {
+            Ok(version) => version,
+            Err(_) => continue,
+        };
+        for (advisory, title) in advisories
+            .iter()
+            .filter(|(a, _)| a.advisory.package == pkg.name)
+        {
+            let fixed = advisory
+                .versions
+                .patched
+                .iter()
+                .chain(advisory.versions.unaffected.iter())
+                .filter_map(|req| semver::VersionReq::parse(req).ok())
+                .any(|req| req.matches(&version));
+            if fixed {
+                continue;
+            }
+            findings.push(Finding {
+                name: pkg.name.clone(),
+                version: pkg.vers.clone(),
+                id: advisory.advisory.id.clone(),
+                title: title.clone(),
+                kind: advisory
+                    .advisory
+                    .informational
+                    .clone()
+                    .unwrap_or_else(|| "vulnerability".to_string()),
+            });
+        }
+    }
+
+    if options.json {
+        config
+            .shell()
+            .warn("`--json` is deprecated, use `--message-format json` instead")?;
+        println!("{}", serde_json::to_string(&findings).unwrap());
+    } else if format == MessageFormat::Json {
+        for finding in findings.iter() {
+            let mut advisory = serde_json::to_value(finding).unwrap();
+            advisory["reason"] = json!("advisory");
+            message::emit(advisory);
+        }
+    } else {
+        for finding in findings.iter() {
+            println!(
+                "{} {}: {} ({}) {}",
+                finding.name, finding.version, finding.id, finding.kind, finding.title
+            );
+        }
+    }
+    let vulnerable = findings
+        .iter()
+        .filter(|f| f.kind == "vulnerability")
+        .count();
+    if vulnerable > 0 {
+        return Err(Error::Policy(format!(
+            "found {} {} in `{}`",
+            vulnerable,
+            if vulnerable == 1 {
+                "vulnerability"
+            } else {
+                "vulnerabilities"
+            },
+            options.path
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+/// Clones the advisory database to `path`, or brings it up to date, only
+/// warning when that fails and an older copy is around.
+fn fetch_db(path: &Path, config: &GlobalContext) -> CargoResult<()> {
+    let result = (|| -> Result<(), git2::Error> {
+        if !path.exists() {
+            git2::Repository::clone(ADVISORY_DB, path)?;
+            return Ok(());
+        }
+        let repo = git2::Repository::open(path)?;
+        repo.find_remote("origin")?.fetch(&["main"], None, None)?;
+        let head = repo.find_reference("FETCH_HEAD")?.peel_to_commit()?;
+        repo.reset(head.as_object(), git2::ResetType::Hard, None)
+    })();
+    match result {
+        Err(e) if path.join("crates").is_dir() => config.shell().warn(format!(
+            "failed to update the advisory database, using the existing copy: {}",
+            e
+        )),
+        Err(e) => Err(anyhow::Error::from(e).context(format!(
+            "failed to fetch the advisory database from `{}`",
+            ADVISORY_DB
+        ))),
+        Ok(()) => Ok(()),
+    }
+}
+
+/// Loads the advisories under `crates/NAME/` of the database, along with
+/// their titles, leaving out withdrawn ones.
+fn load_advisories(dir: &Path) -> CargoResult<Vec<(Advisory, String)>> {
+    let mut advisories = Vec::new();
+    let crates = dir
+        .read_dir()
+        .with_context(|| format!("no advisories in `{}`", dir.display()))?;
+    for krate in crates {
+        let krate = krate?.path();
+        if !krate.is_dir() {
+            continue;
+        }
+        for file in krate.read_dir()? {
+            let file = file?.path();
+            if file.extension().is_none_or(|ext| ext != "md") {
+                continue;
+            }
+            let contents = read(&file)?;
+            let (front, body) = contents
+                .strip_prefix("```toml")
+                .and_then(|rest| rest.split_once("```"))
+                .ok_or_else(|| anyhow::format_err!("invalid advisory `{}`", file.display()))?;
+            let advisory: Advisory = toml::from_str(front)
+                .with_context(|| format!("invalid advisory `{}`", file.display()))?;
+            if advisory.advisory.withdrawn.is_some() {
+                continue;
+            }
+            let title = body
+                .lines()
+                .find_map(|l| l.strip_prefix("# "))
+                .unwrap_or_default()
+                .trim()
+                .to_string();
+            advisories.push((advisory, title));
+        }
+    }
+    Ok(advisories)
+}
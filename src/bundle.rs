@@ -0,0 +1,183 @@
+use crate::{
+    crate_files, index, index_files, provenance, replace_file, sidecar_files, sign, Error,
+};
+use anyhow::Context as _;
+use cargo::util::errors::*;
+use cargo::util::GlobalContext;
+use cargo_util::Sha256;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::prelude::*;
+use std::path::{Component, Path};
+use tar::{Archive, Builder, Header};
+
+/// Name of the first entry of a bundle, listing the sha256 of every other
+/// entry in the format of `sha256sum`.
+const MANIFEST: &str = "MANIFEST";
+
+#[derive(clap::Args)]
+pub struct BundleOptions {
+    /// Path to the local registry
+    pub path: String,
+    /// Path to the bundle file
+    pub file: String,
+}
+
+/// Writes the whole registry to a single gzipped tarball, starting with a
+/// manifest of the checksums of everything in it.
+pub fn bundle(options: &BundleOptions, config: &GlobalContext) -> CargoResult<()> {
+    let root = Path::new(&options.path);
+    let index = index::dir(root);
+    if !index.is_dir() {
+        anyhow::bail!("`{}` isn't a local registry", root.display());
+    }
+    let mut files = index_files(&index)?;
+    if index.join("config.json").is_file() {
+        files.push(index.join("config.json"));
+    }
+    files.extend(crate_files(root)?);
+    files.extend(sidecar_files(root)?);
+    // A signed registry stays verifiable on the other end, and keeps its
+    // provenance.
+    for name in [provenance::FILE, sign::SUMS, sign::SIGNATURE] {
+        if root.join(name).is_file() {
+            files.push(root.join(name));
+        }
+    }
+    files.sort();
+
+    let mut manifest = String::new();
+    for file in files.iter() {
+        let mut sha = Sha256::new();
+        manifest.push_str(&format!(
+            "{}  {}\n",
+            sha.update_path(file)?.finish_hex(),
+            entry_name(root, file)?
+        ));
+    }
+
+    let out = File::create(&options.file)
+        .with_context(|| format!("failed to create `{}`", options.file))?;
+    let mut ar = Builder::new(GzEncoder::new(out, flate2::Compression::best()));
+    ar.mode(tar::HeaderMode::Deterministic);
+    let mut header = Header::new_gnu();
+    header.set_size(manifest.len() as u64);
+    header.set_mode(0o644);
+    ar.append_data(&mut header, MANIFEST, manifest.as_bytes())?;
+    for file in files.iter() {
+        ar.append_path_with_name(file, entry_name(root, file)?)?;
+    }
+    ar.into_inner()?.finish()?;
+
+    let mut sha = Sha256::new();
+    config.shell().status(
+        "Bundled",
+        format!(
+            "{} files to `{}` (sha256 {})",
+            files.len(),
+            options.file,
+            sha.update_path(Path::new(&options.file))?.finish_hex()
+        ),
+    )?;
+    Ok(())
+}
+
+/// Extracts a bundle into the registry, checking every file against the
+/// manifest as it goes. Files that don't match, or aren't in the manifest,
+/// are left out and reported once everything else is extracted.
+pub fn unbundle(options: &BundleOptions, config: &GlobalContext) -> CargoResult<()> {
+    let root = Path::new(&options.path);
+    let file =
+        File::open(&options.file).with_context(|| format!("failed to open `{}`", options.file))?;
+    let mut archive = Archive::new(GzDecoder::new(file));
+    let mut entries = archive.entries()?;
+
+    let mut manifest = match entries.next() {
+        Some(entry) => {
+            let mut entry = entry?;
+            if entry.path()?.to_str() != Some(MANIFEST) {
+                anyhow::bail!("`{}` doesn't start with a manifest", options.file);
+            }
+            let mut contents = String::new();
+            entry.read_to_string(&mut contents)?;
+            parse_manifest(&contents)?
+        }
+        None => anyhow::bail!("`{}` is empty", options.file),
+    };
+
+    let mut failed = Vec::new();
+    let mut extracted = 0;
+    for entry in entries {
+        let mut entry = entry?;
+        let name = entry.path()?.to_string_lossy().into_owned();
+        let mut contents = Vec::new();
+        entry.read_to_end(&mut contents)?;
+        let expected = match manifest.remove(&name) {
+            Some(expected) => expected,
+            None => {
+                failed.push(format!("{} (not in the manifest)", name));
+                continue;
+            }
+        };
+        let mut sha = Sha256::new();
+        if sha.update(&contents).finish_hex() != expected {
+            failed.push(format!("{} (checksum mismatch)", name));
+            continue;
+        }
+        let dst = root.join(&name);
+        fs::create_dir_all(dst.parent().unwrap())?;
+        replace_file(&dst)?;
+        File::create(&dst).and_then(|mut f| f.write_all(&contents))?;
+        extracted += 1;
+    }
+    failed.extend(
+        manifest
+            .into_keys()
+            .map(|name| format!("{} (missing)", name)),
+    );
+
+    config.shell().status(
+        "Unbundled",
+        format!("{} files to `{}`", extracted, root.display()),
+    )?;
+    if !failed.is_empty() {
+        return Err(Error::Integrity(format!(
+            "{} files of `{}` failed verification and weren't extracted:\n  {}",
+            failed.len(),
+            options.file,
+            failed.join("\n  ")
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+/// Parses the manifest of a bundle, rejecting any path that would end up
+/// outside of the registry.
+pub fn parse_manifest(contents: &str) -> CargoResult<BTreeMap<String, String>> {
+    let mut manifest = BTreeMap::new();
+    for line in contents.lines().filter(|l| !l.trim().is_empty()) {
+        let (cksum, name) = line
+            .split_once("  ")
+            .ok_or_else(|| anyhow::format_err!("invalid manifest line: `{}`", line))?;
+        if !Path::new(name)
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)))
+        {
+            anyhow::bail!("invalid path in manifest: `{}`", name);
+        }
+        manifest.insert(name.to_string(), cksum.to_string());
+    }
+    Ok(manifest)
+}
+
+/// Returns the name of `file` in a bundle of the registry at `root`.
+fn entry_name(root: &Path, file: &Path) -> CargoResult<String> {
+    let relative = file.strip_prefix(root).unwrap();
+    relative
+        .to_str()
+        .map(|s| s.replace('\\', "/"))
+        .ok_or_else(|| anyhow::format_err!("non-utf8 path `{}`", file.display()))
+}
@@ -0,0 +1,119 @@
+//! Defaults for the command line options, read from the configuration of the
+//! user, `~/.config/cargo-local-registry/config.toml`, and then from that of
+//! the registry, `.local-registry.toml` at its root:
+//!
+//! ```toml
+//! path = "/srv/registry"
+//! host = "https://my-registry.example.com/index"
+//! policy = "policy.toml"
+//! max-crate-size = "20MB"
+//! ```
+//!
+//! Options given on the command line win over the registry's configuration,
+//! which wins over the user's. Relative paths are relative to the file they
+//! are in.
+
+use crate::{parse_size, read, IndexFormat, Layout, Options};
+use anyhow::Context as _;
+use cargo::util::errors::*;
+use serde::Deserialize;
+use std::env;
+use std::path::{Path, PathBuf};
+
+/// Name of the configuration file at the root of a registry.
+pub const FILE: &str = ".local-registry.toml";
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    /// Registry to use when none is given, only read from the user's
+    /// configuration.
+    path: Option<PathBuf>,
+    host: Option<String>,
+    git: Option<bool>,
+    no_delete: Option<bool>,
+    sign_key: Option<PathBuf>,
+    policy: Option<PathBuf>,
+    keep_versions: Option<Vec<String>>,
+    dl: Option<String>,
+    split_sources: Option<bool>,
+    shard_crates: Option<bool>,
+    store: Option<PathBuf>,
+    max_crate_size: Option<String>,
+    sha256_files: Option<bool>,
+    index_format: Option<IndexFormat>,
+    layout: Option<Layout>,
+}
+
+/// Returns where the configuration of the user lives, under
+/// `$XDG_CONFIG_HOME`, or `~/.config` when that isn't set.
+pub fn user_path() -> Option<PathBuf> {
+    let dir = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME").or_else(|| env::var_os("USERPROFILE"))?)
+            .join(".config"),
+    };
+    Some(dir.join("cargo-local-registry").join("config.toml"))
+}
+
+impl Config {
+    /// Reads the configuration at `path`, if there is one, resolving its
+    /// relative paths against its directory.
+    pub fn load(path: &Path) -> CargoResult<Option<Config>> {
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let mut config: Config = toml::from_str(&read(path)?)
+            .with_context(|| format!("invalid configuration `{}`", path.display()))?;
+        if let Some(ref size) = config.max_crate_size {
+            parse_size(size)
+                .map_err(anyhow::Error::msg)
+                .with_context(|| format!("invalid configuration `{}`", path.display()))?;
+        }
+        let dir = path.parent().unwrap();
+        for p in [
+            &mut config.path,
+            &mut config.sign_key,
+            &mut config.policy,
+            &mut config.store,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            *p = dir.join(&*p);
+        }
+        Ok(Some(config))
+    }
+
+    /// Fills in the registry to use when none is given on the command line.
+    pub fn apply_path(&self, options: &mut Options) {
+        if options.path.is_none() {
+            options.path = self.path.as_ref().map(|p| p.display().to_string());
+        }
+    }
+
+    /// Fills in the options not given on the command line.
+    pub fn apply(self, options: &mut Options) {
+        fn string(p: PathBuf) -> String {
+            p.display().to_string()
+        }
+        options.host = options.host.take().or(self.host);
+        options.git |= self.git.unwrap_or(false);
+        options.sign_key = options.sign_key.take().or(self.sign_key.map(string));
+        options.policy = options.policy.take().or(self.policy.map(string));
+        options.store = options.store.take().or(self.store.map(string));
+        options.dl = options.dl.take().or(self.dl);
+        options.split_sources |= self.split_sources.unwrap_or(false);
+        options.shard_crates |= self.shard_crates.unwrap_or(false);
+        options.sha256_files |= self.sha256_files.unwrap_or(false);
+        if options.keep_versions.is_empty() && !options.no_delete {
+            options.keep_versions = self.keep_versions.unwrap_or_default();
+            options.no_delete = self.no_delete.unwrap_or(false) && options.keep_versions.is_empty();
+        }
+        if options.max_crate_size.is_none() {
+            options.max_crate_size = self.max_crate_size.map(|s| parse_size(&s).unwrap());
+        }
+        options.index_format = options.index_format.or(self.index_format);
+        options.layout = options.layout.or(self.layout);
+    }
+}
@@ -0,0 +1,88 @@
+use std::path::PathBuf;
+
+/// Errors of the library API, telling apart the failures worth handling
+/// differently. Anything else is `Other`, with the whole chain of causes.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// A crate, or a version matching a requirement, doesn't exist, or the
+    /// crates requested can't be resolved together.
+    #[error("failed to resolve dependencies")]
+    Resolve(#[source] anyhow::Error),
+    /// Something failed to download, from a registry or a git repository.
+    #[error("network failure")]
+    Network(#[source] anyhow::Error),
+    /// An index file of the registry doesn't parse.
+    #[error("corrupt index file `{}`: {message}", path.display())]
+    CorruptIndex { path: PathBuf, message: String },
+    /// Crates were refused by `--policy` or `--max-crate-size`, have a
+    /// license the policy doesn't allow, or have vulnerabilities.
+    #[error("{0}")]
+    Policy(String),
+    /// Files don't match their checksums or signature, or the registry
+    /// failed verification.
+    #[error("{0}")]
+    Integrity(String),
+    #[error(transparent)]
+    Other(anyhow::Error),
+}
+
+impl Error {
+    /// The exit code of the command line when it fails with this error.
+    /// These are stable, and documented in the README.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Resolve(_) => 3,
+            Error::Network(_) => 4,
+            Error::Integrity(_) => 5,
+            Error::Policy(_) => 6,
+            Error::CorruptIndex { .. } => 7,
+            Error::Other(_) => 101,
+        }
+    }
+}
+
+impl From<anyhow::Error> for Error {
+    /// Finds out which kind of error `e` is from its causes.
+    fn from(e: anyhow::Error) -> Error {
+        let e = match e.downcast::<Error>() {
+            Ok(e) => return e,
+            Err(e) => e,
+        };
+        if is_resolve(&e) {
+            Error::Resolve(e)
+        } else if is_network(&e) {
+            Error::Network(e)
+        } else {
+            Error::Other(e)
+        }
+    }
+}
+
+/// Returns the exit code of the command line when it fails with `e`, as
+/// `Error::exit_code` does, without losing the context of `e`.
+pub(crate) fn exit_code(e: &anyhow::Error) -> i32 {
+    match e.downcast_ref::<Error>() {
+        Some(e) => e.exit_code(),
+        None if is_resolve(e) => 3,
+        None if is_network(e) => 4,
+        None => 101,
+    }
+}
+
+fn is_resolve(e: &anyhow::Error) -> bool {
+    e.chain()
+        .any(|cause| cause.is::<cargo::core::resolver::ResolveError>())
+}
+
+fn is_network(e: &anyhow::Error) -> bool {
+    e.chain().any(|cause| {
+        cause.is::<curl::Error>()
+            || cause.is::<cargo::util::errors::HttpNotSuccessful>()
+            || cause.downcast_ref::<git2::Error>().is_some_and(|e| {
+                matches!(
+                    e.class(),
+                    git2::ErrorClass::Net | git2::ErrorClass::Http | git2::ErrorClass::Ssl
+                )
+            })
+    })
+}
@@ -0,0 +1,232 @@
+use crate::{crate_files, index, index_files, oci, sidecar_files, walk, Checksum, RegistryConfig};
+use anyhow::Context as _;
+use cargo::util::errors::*;
+use cargo::util::GlobalContext;
+use cargo_util::Sha256;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::prelude::*;
+use std::path::Path;
+use tar::{Archive, Builder};
+
+#[derive(clap::Args)]
+pub struct ExportOptions {
+    /// Path to the local registry
+    pub path: String,
+    /// Directory to export to
+    pub dst: String,
+    /// Format to export to
+    #[arg(long, value_enum)]
+    pub format: ExportFormat,
+    /// URL a static export will be served from, used to build the `dl`
+    /// template
+    #[arg(long)]
+    pub url: Option<String>,
+    /// `dl` template to write to `config.json` instead of one under `--url`
+    #[arg(long, value_name = "TEMPLATE", conflicts_with_all = ["url", "crates_dir"])]
+    pub dl: Option<String>,
+    /// `api` URL to write to `config.json`
+    #[arg(long)]
+    pub api: Option<String>,
+    /// Put the crate files in this subdirectory instead of next to the index
+    #[arg(long, value_name = "DIR")]
+    pub crates_dir: Option<String>,
+    /// Tag to give an OCI export, replacing any manifest with the same tag
+    /// in the layout
+    #[arg(long, default_value = "latest")]
+    pub tag: String,
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+pub enum ExportFormat {
+    /// A sparse registry any static file host can serve as is
+    Static,
+    /// A directory source, as produced by `cargo vendor`
+    VendorDir,
+    /// An OCI image layout holding the registry as an artifact, which can be
+    /// pushed with `oras` or `skopeo`
+    Oci,
+}
+
+pub fn export(options: &ExportOptions, config: &GlobalContext) -> CargoResult<()> {
+    let src = Path::new(&options.path);
+    if !index::dir(src).is_dir() {
+        anyhow::bail!("`{}` isn't a local registry", src.display());
+    }
+    let dst = Path::new(&options.dst);
+    fs::create_dir_all(dst).with_context(|| format!("failed to create `{}`", dst.display()))?;
+    match options.format {
+        ExportFormat::Static => export_static(src, dst, options)?,
+        ExportFormat::VendorDir => export_vendor_dir(src, dst)?,
+        ExportFormat::Oci => export_oci(src, dst, &options.tag)?,
+    }
+    config.shell().status(
+        "Exported",
+        format!("`{}` to `{}`", src.display(), dst.display()),
+    )?;
+    Ok(())
+}
+
+/// Lays the registry out the way Cargo expects a sparse registry to be
+/// served: `config.json` and the index files at the root, and the crate
+/// files wherever the `dl` template points.
+fn export_static(src: &Path, dst: &Path, options: &ExportOptions) -> CargoResult<()> {
+    let index = index::dir(src);
+    for file in index_files(&index)? {
+        let to = dst.join(file.strip_prefix(&index).unwrap());
+        fs::create_dir_all(to.parent().unwrap())?;
+        fs::copy(&file, &to)?;
+    }
+
+    let crates_dst = match options.crates_dir {
+        Some(ref dir) => {
+            // Index files live under one and two character directories, keep
+            // clear of those.
+            if dir.len() <= 2 || dir.contains(['/', '\\']) {
+                anyhow::bail!("invalid crates directory `{}`", dir);
+            }
+            dst.join(dir)
+        }
+        None => dst.to_path_buf(),
+    };
+    fs::create_dir_all(&crates_dst)?;
+    for file in crate_files(src)? {
+        let to = crates_dst.join(file.file_name().unwrap());
+        fs::copy(&file, &to).with_context(|| {
+            format!("failed to copy `{}` to `{}`", file.display(), to.display())
+        })?;
+    }
+    for file in sidecar_files(src)? {
+        fs::copy(&file, crates_dst.join(file.file_name().unwrap()))?;
+    }
+
+    let dl = match (&options.dl, &options.url) {
+        (Some(dl), _) => dl.clone(),
+        (None, Some(url)) => {
+            let mut dl = url.trim_end_matches('/').to_string();
+            if let Some(ref dir) = options.crates_dir {
+                dl.push('/');
+                dl.push_str(dir);
+            }
+            dl.push_str("/{crate}-{version}.crate");
+            dl
+        }
+        (None, None) => anyhow::bail!("either `--url` or `--dl` is needed for a static export"),
+    };
+    let config = serde_json::to_string(&RegistryConfig {
+        dl: &dl,
+        api: options.api.as_deref(),
+    })
+    .unwrap();
+    File::create(dst.join("config.json")).and_then(|mut f| f.write_all(config.as_bytes()))?;
+    Ok(())
+}
+
+/// Unpacks every crate into a `NAME-VERSION` directory with the
+/// `.cargo-checksum.json` Cargo checks directory sources against, as
+/// `cargo vendor --versioned-dirs` would.
+fn export_vendor_dir(src: &Path, dst: &Path) -> CargoResult<()> {
+    for file in crate_files(src)? {
+        let dir = dst.join(file.file_stem().unwrap());
+        if dir.exists() {
+            fs::remove_dir_all(&dir)?;
+        }
+        let mut archive = Archive::new(GzDecoder::new(File::open(&file)?));
+        archive
+            .unpack(dst)
+            .with_context(|| format!("failed to unpack `{}`", file.display()))?;
+        if !dir.is_dir() {
+            anyhow::bail!(
+                "`{}` doesn't unpack to `{}`",
+                file.display(),
+                dir.file_name().unwrap().to_string_lossy()
+            );
+        }
+
+        let mut files = BTreeMap::new();
+        for path in walk(&dir)? {
+            let relative = path.strip_prefix(&dir).unwrap();
+            let relative = relative
+                .to_str()
+                .ok_or_else(|| anyhow::format_err!("non-utf8 path in `{}`", file.display()))?;
+            let mut sha = Sha256::new();
+            files.insert(
+                relative.replace('\\', "/"),
+                sha.update_path(&path)?.finish_hex(),
+            );
+        }
+        let mut sha = Sha256::new();
+        let checksum = Checksum {
+            files,
+            package: Some(sha.update_path(&file)?.finish_hex()),
+        };
+        let json = serde_json::to_string(&checksum).unwrap();
+        File::create(dir.join(".cargo-checksum.json"))
+            .and_then(|mut f| f.write_all(json.as_bytes()))?;
+    }
+    Ok(())
+}
+
+/// Adds the registry to the OCI image layout at `dst` as a manifest tagged
+/// `tag`, with the index as its first layer and each crate file as a layer.
+fn export_oci(src: &Path, dst: &Path, tag: &str) -> CargoResult<()> {
+    let mut ar = Builder::new(GzEncoder::new(Vec::new(), flate2::Compression::best()));
+    ar.mode(tar::HeaderMode::Deterministic);
+    let index = index::dir(src);
+    let mut files = index_files(&index)?;
+    files.sort();
+    for file in files {
+        let name = Path::new("index").join(file.strip_prefix(&index).unwrap());
+        ar.append_path_with_name(&file, name)?;
+    }
+    let mut index_layer = oci::write_blob(dst, oci::LAYER_TYPE, &ar.into_inner()?.finish()?)?;
+    index_layer
+        .annotations
+        .insert(oci::TITLE.to_string(), "index".to_string());
+    index_layer
+        .annotations
+        .insert(oci::UNPACK.to_string(), "true".to_string());
+
+    let mut layers = vec![index_layer];
+    for file in crate_files(src)? {
+        let mut layer = oci::write_blob(dst, oci::LAYER_TYPE, &fs::read(&file)?)?;
+        layer.annotations.insert(
+            oci::TITLE.to_string(),
+            file.file_name().unwrap().to_string_lossy().into_owned(),
+        );
+        layers.push(layer);
+    }
+    let manifest = oci::Manifest {
+        schema_version: 2,
+        media_type: oci::MANIFEST_TYPE.to_string(),
+        artifact_type: oci::ARTIFACT_TYPE.to_string(),
+        config: oci::write_blob(dst, oci::EMPTY_TYPE, b"{}")?,
+        layers,
+    };
+    let json = serde_json::to_vec(&manifest).unwrap();
+    let mut desc = oci::write_blob(dst, oci::MANIFEST_TYPE, &json)?;
+    desc.annotations
+        .insert(oci::REF_NAME.to_string(), tag.to_string());
+
+    let index_json = dst.join("index.json");
+    let mut index = match fs::read(&index_json) {
+        Ok(contents) => serde_json::from_slice::<oci::Index>(&contents)
+            .with_context(|| format!("invalid `{}`", index_json.display()))?,
+        Err(_) => oci::Index {
+            schema_version: 2,
+            media_type: Some(oci::INDEX_TYPE.to_string()),
+            manifests: Vec::new(),
+        },
+    };
+    index
+        .manifests
+        .retain(|m| m.annotations.get(oci::REF_NAME).map(|t| t.as_str()) != Some(tag));
+    index.manifests.push(desc);
+    File::create(dst.join("oci-layout"))
+        .and_then(|mut f| f.write_all(br#"{"imageLayoutVersion":"1.0.0"}"#))?;
+    let json = serde_json::to_vec(&index).unwrap();
+    File::create(&index_json).and_then(|mut f| f.write_all(&json))?;
+    Ok(())
+}
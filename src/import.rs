@@ -0,0 +1,254 @@
+use crate::provenance::{self, Provenance};
+use crate::{
+    crate_path, index, oci, read, registry_pkg, replace_file, walk, write_index_entry, Checksum,
+    Error, RegistryPackage,
+};
+use anyhow::Context as _;
+use cargo::core::SourceId;
+use cargo::util::errors::*;
+use cargo::util::GlobalContext;
+use cargo_util::Sha256;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use tar::{Archive, Builder};
+
+#[derive(clap::Args)]
+pub struct ImportOptions {
+    /// Path to the local registry
+    pub path: String,
+    /// Directory to import from
+    pub src: String,
+    /// Format to import from
+    #[arg(long, value_enum)]
+    pub format: ImportFormat,
+    /// Tag of the manifest to import from an OCI image layout
+    #[arg(long, default_value = "latest")]
+    pub tag: String,
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+pub enum ImportFormat {
+    /// A directory source, as produced by `cargo vendor`
+    VendorDir,
+    /// An OCI image layout written by `export --format oci`
+    Oci,
+}
+
+pub fn import(
+    options: &ImportOptions,
+    registry_id: &SourceId,
+    config: &GlobalContext,
+) -> CargoResult<BTreeMap<SourceId, PathBuf>> {
+    let src = config.cwd().join(&options.src);
+    let dst = Path::new(&options.path);
+    let added = match options.format {
+        ImportFormat::VendorDir => import_vendor_dir(&src, dst, config)?,
+        ImportFormat::Oci => import_oci(&src, dst, &options.tag, config)?,
+    };
+    provenance::record(dst, added)?;
+
+    let mut sources = BTreeMap::new();
+    sources.insert(
+        *registry_id,
+        dst.canonicalize().unwrap_or(dst.to_path_buf()),
+    );
+    Ok(sources)
+}
+
+/// Packs every package of a directory source back into a crate file and
+/// adds it to the index, after checking it against its
+/// `.cargo-checksum.json`.
+///
+/// The archives are rebuilt, so their checksums differ from those of the
+/// original crate files and lock files pointing at those need updating.
+fn import_vendor_dir(
+    src: &Path,
+    dst: &Path,
+    config: &GlobalContext,
+) -> CargoResult<Vec<Provenance>> {
+    let mut dirs = Vec::new();
+    let mut added = Vec::new();
+    for entry in src
+        .read_dir()
+        .with_context(|| format!("failed to read `{}`", src.display()))?
+    {
+        let path = entry?.path();
+        if path.join("Cargo.toml").is_file() {
+            dirs.push(path);
+        }
+    }
+    dirs.sort();
+
+    for dir in dirs {
+        let checksum_file = dir.join(".cargo-checksum.json");
+        if checksum_file.exists() {
+            let checksum: Checksum = serde_json::from_str(&read(&checksum_file)?)
+                .with_context(|| format!("invalid `{}`", checksum_file.display()))?;
+            for (file, expected) in checksum.files.iter() {
+                let mut sha = Sha256::new();
+                let actual = sha.update_path(dir.join(file))?.finish_hex();
+                if actual != *expected {
+                    return Err(Error::Integrity(format!(
+                        "checksum mismatch for `{}` in `{}`: expected {}, found {}",
+                        file,
+                        dir.display(),
+                        expected,
+                        actual
+                    ))
+                    .into());
+                }
+            }
+        }
+
+        let pkg =
+            cargo::ops::read_package(&dir.join("Cargo.toml"), SourceId::for_path(&dir)?, config)?;
+        let prefix = format!("{}-{}", pkg.name(), pkg.version());
+        let file = crate_path(dst, &pkg.name(), &pkg.version().to_string());
+        fs::create_dir_all(file.parent().unwrap())?;
+        replace_file(&file)?;
+        let mut ar = Builder::new(GzEncoder::new(
+            File::create(&file)?,
+            flate2::Compression::best(),
+        ));
+        ar.mode(tar::HeaderMode::Deterministic);
+        let mut files = walk(&dir)?;
+        files.sort();
+        for path in files.iter().filter(|p| **p != checksum_file) {
+            let relative = path.strip_prefix(&dir).unwrap();
+            ar.append_path_with_name(path, Path::new(&prefix).join(relative))?;
+        }
+        ar.into_inner()?.finish()?;
+
+        let mut sha = Sha256::new();
+        let cksum = sha.update_path(&file)?.finish_hex();
+        let entry = registry_pkg(&pkg, cksum);
+        write_index_entry(dst, &entry)?;
+        added.push(Provenance::new(
+            &entry.name,
+            &entry.vers,
+            &entry.cksum,
+            dir.display().to_string(),
+            "import",
+        ));
+        config.shell().status("Imported", pkg.package_id())?;
+    }
+    Ok(added)
+}
+
+/// Writes the crate files and index entries of the artifact tagged `tag` in
+/// the OCI image layout at `src` to the registry, checking every blob against
+/// its digest and every crate file against its index entry. Index entries are
+/// merged with those already in the registry.
+fn import_oci(
+    src: &Path,
+    dst: &Path,
+    tag: &str,
+    config: &GlobalContext,
+) -> CargoResult<Vec<Provenance>> {
+    let index_json = src.join("index.json");
+    let index: oci::Index = serde_json::from_str(&read(&index_json)?)
+        .with_context(|| format!("invalid `{}`", index_json.display()))?;
+    let desc = index
+        .manifests
+        .iter()
+        .find(|m| m.annotations.get(oci::REF_NAME).map(|t| t.as_str()) == Some(tag))
+        .ok_or_else(|| {
+            anyhow::format_err!("no manifest tagged `{}` in `{}`", tag, src.display())
+        })?;
+    let manifest: oci::Manifest = serde_json::from_slice(&oci::read_blob(src, desc)?)
+        .with_context(|| format!("invalid manifest `{}`", desc.digest))?;
+    if manifest.artifact_type != oci::ARTIFACT_TYPE {
+        anyhow::bail!(
+            "`{}:{}` isn't a registry, its artifact type is `{}`",
+            src.display(),
+            tag,
+            manifest.artifact_type
+        );
+    }
+
+    // Everything is checked before anything is written: the index entries,
+    // which the crate files are named after, and every crate file against
+    // both its digest and the checksum its entry records.
+    let mut pkgs = Vec::new();
+    let mut crate_layers = Vec::new();
+    for layer in manifest.layers.iter() {
+        if !layer.annotations.contains_key(oci::UNPACK) {
+            crate_layers.push(layer);
+            continue;
+        }
+        let contents = oci::read_blob(src, layer)?;
+        let mut archive = Archive::new(GzDecoder::new(&contents[..]));
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let mut lines = String::new();
+            entry.read_to_string(&mut lines)?;
+            for line in lines.lines().filter(|l| !l.trim().is_empty()) {
+                let pkg: RegistryPackage = serde_json::from_str(line).with_context(|| {
+                    format!(
+                        "invalid index entry in `{}`",
+                        entry.path().unwrap().display()
+                    )
+                })?;
+                if !index::is_valid_name(&pkg.name) {
+                    anyhow::bail!("invalid crate name `{}` in the index", pkg.name);
+                }
+                if semver::Version::parse(&pkg.vers).is_err() {
+                    anyhow::bail!(
+                        "invalid version `{}` of `{}` in the index",
+                        pkg.vers,
+                        pkg.name
+                    );
+                }
+                pkgs.push(pkg);
+            }
+        }
+    }
+    let crates = pkgs
+        .iter()
+        .map(|pkg| (format!("{}-{}.crate", pkg.name, pkg.vers), pkg))
+        .collect::<HashMap<_, _>>();
+    let mut files = Vec::new();
+    for layer in crate_layers {
+        let title = layer.annotations.get(oci::TITLE).map(|t| t.as_str());
+        let (title, pkg) = match title.and_then(|t| crates.get_key_value(t)) {
+            Some(found) => found,
+            None => anyhow::bail!("layer `{}` isn't a crate file of the index", layer.digest),
+        };
+        let mut sha = Sha256::new();
+        let actual = sha.update(&oci::read_blob(src, layer)?).finish_hex();
+        if actual != pkg.cksum {
+            return Err(Error::Integrity(format!(
+                "checksum mismatch for `{}`: the index says {}, found {}",
+                title, pkg.cksum, actual
+            ))
+            .into());
+        }
+        files.push((layer, title, *pkg));
+    }
+
+    fs::create_dir_all(index::dir(dst))?;
+    for pkg in pkgs.iter() {
+        write_index_entry(dst, pkg)?;
+    }
+    let mut added = Vec::new();
+    for (layer, title, pkg) in files {
+        let file = crate_path(dst, &pkg.name, &pkg.vers);
+        fs::create_dir_all(file.parent().unwrap())?;
+        let contents = oci::read_blob(src, layer)?;
+        replace_file(&file)?;
+        File::create(&file).and_then(|mut f| f.write_all(&contents))?;
+        added.push(Provenance::new(
+            &pkg.name,
+            &pkg.vers,
+            &pkg.cksum,
+            format!("{}:{}", src.display(), tag),
+            "import",
+        ));
+        config.shell().status("Imported", title)?;
+    }
+    Ok(added)
+}
@@ -0,0 +1,153 @@
+//! The index of a registry: one file per crate, sharded by name, holding a
+//! line of JSON for each version of the crate.
+
+use crate::{index_files, Error};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The entry of a crate version in the index.
+#[derive(Clone, Deserialize, Serialize)]
+#[non_exhaustive]
+pub struct RegistryPackage {
+    pub name: String,
+    pub vers: String,
+    pub deps: Vec<RegistryDependency>,
+    /// The sha256 of the crate file.
+    pub cksum: String,
+    pub features: BTreeMap<String, Vec<String>>,
+    pub yanked: Option<bool>,
+    /// The fields not listed above, like `v` or `features2`, kept as they are
+    /// when the entry is written back.
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(Clone, Eq, PartialEq, Deserialize, Serialize)]
+#[non_exhaustive]
+pub struct RegistryDependency {
+    /// The name of the dependency in the manifest, which is its package
+    /// name unless `package` is set.
+    pub name: String,
+    pub req: String,
+    pub features: Vec<String>,
+    pub optional: bool,
+    pub default_features: bool,
+    pub target: Option<String>,
+    /// `dev` or `build`, or none for normal dependencies.
+    pub kind: Option<String>,
+    pub package: Option<String>,
+    /// The fields not listed above, kept as they are.
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+impl RegistryPackage {
+    /// Parses a line of an index file.
+    pub fn parse(line: &str) -> serde_json::Result<RegistryPackage> {
+        serde_json::from_str(line)
+    }
+
+    /// Serializes the entry as a line of an index file, without the newline.
+    pub fn to_line(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+}
+
+/// Returns the directories the index entries of crate `name` are sharded
+/// under, e.g. `3/s` for `syn` or `se/rd` for `serde`. Names are counted in
+/// characters, so that those that aren't ASCII don't split one in two.
+pub fn prefix(name: &str) -> String {
+    let chars = name.chars().collect::<Vec<_>>();
+    let part = |range: std::ops::Range<usize>| chars[range].iter().collect::<String>();
+    match chars.len() {
+        1 => "1".to_string(),
+        2 => "2".to_string(),
+        3 => format!("3/{}", part(0..1)),
+        _ => format!("{}/{}", part(0..2), part(2..4)),
+    }
+}
+
+/// Returns the directory holding the index of the registry at `root`:
+/// `index/`, or the root itself for registries laid out like the crates.io
+/// index, with `config.json` at the root.
+pub fn dir(root: &Path) -> PathBuf {
+    let index = root.join("index");
+    if !index.is_dir() && root.join("config.json").is_file() {
+        root.to_path_buf()
+    } else {
+        index
+    }
+}
+
+/// Returns where the index entries of crate `name` live in the registry at
+/// `root`.
+pub fn path(root: &Path, name: &str) -> PathBuf {
+    let name = name.to_lowercase();
+    dir(root).join(prefix(&name)).join(name)
+}
+
+/// Reads the entries of the index file at `path`, none if it doesn't exist.
+pub fn read_file(path: &Path) -> Result<Vec<RegistryPackage>, Error> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => {
+            let e = anyhow::Error::from(e).context(format!("failed to read `{}`", path.display()));
+            return Err(Error::Other(e));
+        }
+    };
+    contents
+        .lines()
+        .filter(|l| !l.trim().is_empty())
+        .map(|line| parse_line(path, line))
+        .collect()
+}
+
+/// Adds `pkg` to `entries`, replacing the entry of the same version if there
+/// is one.
+pub fn merge(entries: &mut Vec<RegistryPackage>, pkg: RegistryPackage) {
+    entries.retain(|e| e.vers != pkg.vers);
+    entries.push(pkg);
+}
+
+/// Writes `entries` to the index file at `path`, in the order Cargo's own
+/// tools would, creating its directories as needed.
+pub fn write_file(path: &Path, entries: &[RegistryPackage]) -> Result<(), Error> {
+    let mut lines = entries.iter().map(|e| e.to_line()).collect::<Vec<_>>();
+    lines.sort();
+    fs::create_dir_all(path.parent().unwrap())
+        .and_then(|()| fs::write(path, lines.join("\n")))
+        .map_err(|e| {
+            let e = anyhow::Error::from(e).context(format!("failed to write `{}`", path.display()));
+            Error::Other(e)
+        })
+}
+
+/// Reads every entry of the index of the registry at `root`.
+pub fn entries(root: &Path) -> Result<Vec<RegistryPackage>, Error> {
+    let mut entries = Vec::new();
+    for path in index_files(&dir(root)).map_err(Error::from)? {
+        entries.extend(read_file(&path)?);
+    }
+    Ok(entries)
+}
+
+/// Tells whether `name` can be the name of a crate: ASCII letters, digits,
+/// `-` and `_`, which keeps it from escaping the index when it becomes a path.
+pub(crate) fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Parses a line of the index file at `path`.
+pub(crate) fn parse_line(path: &Path, line: &str) -> Result<RegistryPackage, Error> {
+    RegistryPackage::parse(line).map_err(|e| Error::CorruptIndex {
+        path: path.to_path_buf(),
+        message: e.to_string(),
+    })
+}
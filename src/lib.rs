@@ -0,0 +1,1746 @@
+use anyhow::Context as _;
+use cargo::core::dependency::DepKind;
+use cargo::core::resolver::Resolve;
+use cargo::core::{Package, PackageId, PackageSet, SourceId, Workspace};
+use cargo::sources::PathSource;
+use cargo::util::errors::*;
+use cargo::util::GlobalContext;
+use cargo_platform::Platform;
+use cargo_util::Sha256;
+use clap::Parser as _;
+use flate2::write::GzEncoder;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::env;
+use std::fs::{self, File};
+use std::io;
+use std::io::prelude::*;
+use std::path::{self, Path, PathBuf};
+use std::sync::Arc;
+use tar::{Builder, Header};
+use url::Url;
+
+mod add;
+mod audit;
+mod bundle;
+mod config;
+mod error;
+mod export;
+mod import;
+pub mod index;
+mod licenses;
+mod message;
+mod oci;
+mod policy;
+mod progress;
+mod provenance;
+mod publish;
+mod registry;
+mod sbom;
+mod sign;
+mod snapshot;
+mod summary;
+
+pub use error::Error;
+pub use index::{RegistryDependency, RegistryPackage};
+use message::MessageFormat;
+pub use progress::{Event, Progress};
+pub use registry::{Registry, SyncOptions};
+use snapshot::SnapshotCommand;
+
+#[derive(clap::Parser)]
+#[command(
+    version,
+    about,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
+struct Options {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Sync the registry with LOCK
+    #[arg(short, long)]
+    sync: Option<String>,
+    /// Path to the Cargo.toml of the workspace LOCK belongs to, defaults to
+    /// the one next to LOCK
+    #[arg(long, value_name = "PATH", requires = "sync")]
+    manifest_path: Option<String>,
+    /// Registry index to sync with
+    #[arg(long, env = "CARGO_LOCAL_REGISTRY_HOST")]
+    host: Option<String>,
+    /// Vendor git dependencies as well
+    #[arg(long, default_value_t = false)]
+    git: bool,
+    /// Use verbose output, at level N if given
+    #[arg(
+        short,
+        long,
+        value_name = "N",
+        num_args = 0..=1,
+        default_value_t,
+        default_missing_value = "1",
+        global = true
+    )]
+    verbose: u32,
+    /// No output printed to stdout
+    #[arg(short, long, default_value_t = false, global = true)]
+    quiet: bool,
+    /// Coloring: auto, always, never
+    #[arg(short, long, global = true)]
+    color: Option<String>,
+    /// Fail instead of updating LOCK when syncing. `add` resolves its own
+    /// throwaway package, so it has nothing to lock
+    #[arg(long, default_value_t = false, global = true)]
+    locked: bool,
+    /// Run without accessing the network, from what Cargo has downloaded
+    #[arg(long, default_value_t = false, global = true)]
+    offline: bool,
+    /// Both `--locked` and `--offline`, when syncing
+    #[arg(long, default_value_t = false, global = true)]
+    frozen: bool,
+    /// Output format of `--sync`, `add`, `verify`, `audit` and `info`
+    #[arg(
+        long,
+        value_enum,
+        value_name = "FMT",
+        default_value_t = MessageFormat::Human,
+        global = true
+    )]
+    message_format: MessageFormat,
+    /// Don't delete older crates in the local registry directory
+    #[arg(
+        long,
+        value_name = "BOOL",
+        num_args = 0..=1,
+        default_value_t = false,
+        default_missing_value = "true",
+        action = clap::ArgAction::Set
+    )]
+    no_delete: bool,
+    /// Secret key to sign the registry, or an export of it, with
+    #[arg(
+        long,
+        value_name = "FILE",
+        global = true,
+        env = "CARGO_LOCAL_REGISTRY_SIGN_KEY"
+    )]
+    sign_key: Option<String>,
+    /// Policy file restricting what may enter the registry
+    #[arg(
+        long,
+        value_name = "FILE",
+        global = true,
+        env = "CARGO_LOCAL_REGISTRY_POLICY"
+    )]
+    policy: Option<String>,
+    /// Keep up to N versions of each crate instead of deleting older ones,
+    /// optionally only for one crate with NAME=N
+    #[arg(long, value_name = "[NAME=]N", conflicts_with = "no_delete")]
+    keep_versions: Vec<String>,
+    /// Crate files are hosted externally at this `dl` URL template, only
+    /// write the index
+    #[arg(long, value_name = "TEMPLATE")]
+    dl: Option<String>,
+    /// sha256sum-style manifest to verify externally hosted crate files
+    /// against instead of downloading them
+    #[arg(long, value_name = "FILE", requires = "dl")]
+    dl_manifest: Option<String>,
+    /// Put crates from alternative registries in their own local registry
+    /// under PATH instead of merging them with crates.io
+    #[arg(long, default_value_t = false)]
+    split_sources: bool,
+    /// Put crate files under `crates/`, sharded like the index, instead of
+    /// next to it. Registries stay sharded once they are
+    #[arg(long, default_value_t = false)]
+    shard_crates: bool,
+    /// Keep crate files in this content-addressed store and hard link them
+    /// into the registry, so registries sharing a store share their files
+    #[arg(
+        long,
+        value_name = "DIR",
+        env = "CARGO_LOCAL_REGISTRY_STORE",
+        global = true
+    )]
+    store: Option<String>,
+    /// Refuse to add crates larger than SIZE, e.g. `20MB` or `512KiB`
+    #[arg(
+        long,
+        value_name = "SIZE",
+        value_parser = parse_size,
+        global = true,
+        env = "CARGO_LOCAL_REGISTRY_MAX_CRATE_SIZE"
+    )]
+    max_crate_size: Option<u64>,
+    /// Write a `.sha256` file in the format of `sha256sum` next to every
+    /// crate file. Registries keep them once they have them
+    #[arg(long, default_value_t = false, global = true)]
+    sha256_files: bool,
+    /// Format of the index: a plain directory for `local-registry` sources,
+    /// the default, or a git repository with a `config.json`, committed on
+    /// each sync
+    #[arg(long, value_enum)]
+    index_format: Option<IndexFormat>,
+    /// Where the index files go in a new registry, defaults to `index`.
+    /// Existing registries keep their layout
+    #[arg(long, value_enum, requires = "dl")]
+    layout: Option<Layout>,
+
+    /// Path to the local registry, defaults to the `path` of the user's
+    /// configuration. Also the registry of subcommands not given one
+    #[arg(env = "CARGO_LOCAL_REGISTRY_PATH")]
+    path: Option<String>,
+
+    #[arg(skip)]
+    progress: Option<Arc<dyn Progress>>,
+}
+
+#[derive(Clone, Copy, Default, PartialEq, clap::ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum IndexFormat {
+    /// A plain directory, for `local-registry` sources
+    #[default]
+    Local,
+    /// A git repository with a `config.json`, committed on each sync
+    Git,
+}
+
+#[derive(Clone, Copy, Default, PartialEq, clap::ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Layout {
+    /// Index files under `index/`, as `local-registry` sources need
+    #[default]
+    Index,
+    /// Index files at the root, like the crates.io index, for registries
+    /// whose crate files are hosted elsewhere with `--dl`
+    Root,
+}
+
+#[derive(clap::Subcommand)]
+enum Command {
+    /// Add crates and everything they depend on to the registry
+    Add(add::AddOptions),
+    /// Report crates of the registry with RustSec advisories
+    Audit(audit::AuditOptions),
+    /// Write a software bill of materials of the registry
+    Sbom(sbom::SbomOptions),
+    /// Write the registry to a single checksummed file
+    Bundle(bundle::BundleOptions),
+    /// Extract a bundle into the registry, checking it along the way
+    Unbundle(bundle::BundleOptions),
+    /// Export the registry in another format
+    Export(export::ExportOptions),
+    /// Import crates into the registry from another format
+    Import(import::ImportOptions),
+    /// Summarize the licenses of the crates in the registry
+    Licenses(licenses::LicensesOptions),
+    /// Package the members of a workspace and add them to the registry
+    PublishLocal(publish::PublishLocalOptions),
+    /// Generate a key pair to sign registries with
+    Keygen(sign::KeygenOptions),
+    /// Check the layout of the registry, and its files against its signed
+    /// manifest
+    Verify(sign::VerifyOptions),
+    /// Show where the versions of a crate in the registry came from
+    Info(provenance::InfoOptions),
+    /// Record the state of the registry under a name, and list those recorded
+    Snapshot(snapshot::SnapshotOptions),
+}
+
+#[derive(Serialize)]
+struct RegistryConfig<'a> {
+    dl: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    api: Option<&'a str>,
+}
+
+/// Contents of the `.cargo-checksum.json` of a directory source.
+#[derive(Deserialize, Serialize)]
+struct Checksum {
+    files: BTreeMap<String, String>,
+    package: Option<String>,
+}
+
+/// How many versions of each crate to keep around when syncing, see
+/// `--keep-versions`. Without a default, crates with no override keep all
+/// their versions.
+#[derive(Default)]
+struct Retention {
+    default: Option<usize>,
+    overrides: HashMap<String, usize>,
+}
+
+impl Retention {
+    fn parse(specs: &[String]) -> CargoResult<Option<Retention>> {
+        if specs.is_empty() {
+            return Ok(None);
+        }
+        let mut retention = Retention::default();
+        for spec in specs {
+            let (name, n) = match spec.split_once('=') {
+                Some((name, n)) => (Some(name), n),
+                None => (None, spec.as_str()),
+            };
+            let n = n
+                .parse()
+                .with_context(|| format!("invalid `--keep-versions` value: `{}`", spec))?;
+            match name {
+                Some(name) => {
+                    retention.overrides.insert(name.to_lowercase(), n);
+                }
+                None => retention.default = Some(n),
+            }
+        }
+        Ok(Some(retention))
+    }
+
+    fn versions_for(&self, name: &str) -> Option<usize> {
+        self.overrides
+            .get(&name.to_lowercase())
+            .cloned()
+            .or(self.default)
+    }
+}
+
+/// Builds the Cargo context the command line runs with, which, as this does
+/// the vendoring itself, ignores any `source` replacement in the Cargo
+/// configuration and goes straight to the source, e.g. crates.io.
+pub fn global_context() -> Result<GlobalContext, Error> {
+    let config_orig = GlobalContext::default()?;
+    let mut values = config_orig.values()?.clone();
+    values.remove("source");
+    let config = GlobalContext::default()?;
+    config.set_values(values)?;
+    Ok(config)
+}
+
+/// Runs the command line, as the `cargo-local-registry` binary does.
+#[doc(hidden)]
+pub fn main() {
+    env_logger::init();
+
+    let mut config = global_context().unwrap();
+
+    // When invoked as `cargo local-registry`, cargo passes the subcommand name
+    // as the first argument, so skip over it.
+    let mut args = env::args_os().collect::<Vec<_>>();
+    if args.get(1).and_then(|s| s.to_str()) == Some("local-registry") {
+        args.remove(1);
+    }
+    let options = Options::parse_from(args);
+    let json = options.message_format == MessageFormat::Json;
+    let result = real_main(options, &mut config);
+    if let Err(e) = result {
+        let code = error::exit_code(&e);
+        if json {
+            message::emit(serde_json::json!({
+                "reason": "error",
+                "message": format!("{:#}", e),
+                "code": code,
+            }));
+        }
+        cargo::exit_with_error(CliError::new(e, code), &mut config.shell());
+    }
+}
+
+fn real_main(mut options: Options, config: &mut GlobalContext) -> CargoResult<()> {
+    if matches!(options.command, Some(Command::Add(_))) && (options.locked || options.frozen) {
+        anyhow::bail!(
+            "`--locked` and `--frozen` only apply to `--sync`, `add` has no lock file \
+             (use `--offline` to add crates Cargo has already downloaded)"
+        );
+    }
+    config.configure(
+        options.verbose,
+        options.quiet,
+        options.color.as_deref(),
+        options.frozen,
+        options.locked,
+        options.offline,
+        /* target dir = */ &None,
+        /* unstable flags = */ &[],
+        /* cli_config = */ &[],
+    )?;
+
+    if options.progress.is_none() {
+        options.progress = match options.message_format {
+            MessageFormat::Json => Some(Arc::new(message::JsonMessages)),
+            MessageFormat::Human if options.quiet => None,
+            MessageFormat::Human => Some(Arc::new(progress::Bar::default())),
+        };
+    }
+    let summary = Arc::new(summary::Summary::new(options.progress.take()));
+    options.progress = Some(summary.clone());
+    if let Some(Command::Keygen(ref keygen)) = options.command {
+        return sign::keygen(keygen, config);
+    }
+    let user_config = match config::user_path() {
+        Some(path) => config::Config::load(&path)?,
+        None => None,
+    };
+    if let Some(ref user_config) = user_config {
+        user_config.apply_path(&mut options);
+    }
+    if let Some(ref mut command) = options.command {
+        default_registry(command, options.path.as_deref())?;
+    }
+    let path = match options.command {
+        Some(Command::Add(ref add)) => Path::new(&add.path),
+        Some(Command::PublishLocal(ref publish)) => Path::new(&publish.path),
+        Some(Command::Export(ref export)) => Path::new(&export.path),
+        Some(Command::Audit(ref audit)) => Path::new(&audit.path),
+        Some(Command::Sbom(ref sbom)) => Path::new(&sbom.path),
+        Some(Command::Licenses(ref licenses)) => Path::new(&licenses.path),
+        Some(Command::Verify(ref verify)) => Path::new(&verify.path),
+        Some(Command::Info(ref info)) => Path::new(&info.path),
+        Some(Command::Bundle(ref bundle)) | Some(Command::Unbundle(ref bundle)) => {
+            Path::new(&bundle.path)
+        }
+        Some(Command::Import(ref import)) => Path::new(&import.path),
+        Some(Command::Snapshot(ref snapshot)) => Path::new(snapshot.path()),
+        Some(Command::Keygen(_)) => unreachable!(),
+        None => match options.path {
+            Some(ref path) => Path::new(path),
+            None => anyhow::bail!("no registry path given, and none configured"),
+        },
+    }
+    .to_path_buf();
+    if let Some(registry_config) = config::Config::load(&path.join(config::FILE))? {
+        registry_config.apply(&mut options);
+    }
+    if let Some(user_config) = user_config {
+        user_config.apply(&mut options);
+    }
+    let dry_run = matches!(options.command, Some(Command::Add(ref add)) if add.dry_run);
+    let read_only = matches!(
+        options.command,
+        Some(Command::Export(_))
+            | Some(Command::Bundle(_))
+            | Some(Command::Audit(_))
+            | Some(Command::Sbom(_))
+            | Some(Command::Licenses(_))
+            | Some(Command::Verify(_))
+            | Some(Command::Info(_))
+            | Some(Command::Snapshot(_))
+    );
+    if !dry_run && !read_only {
+        create_index(&path, &options)?;
+    }
+    let index = index::dir(&path);
+    let id = match options.host {
+        Some(ref s) => SourceId::for_registry(&Url::parse(s)?)?,
+        None => SourceId::crates_io_maybe_sparse_http(config)?,
+    };
+
+    let sources = match options.command {
+        Some(Command::Add(ref add)) => {
+            add::add(add, &id, &options, config).with_context(|| "failed to add crates")?
+        }
+        Some(Command::Audit(ref audit)) => {
+            return audit::audit(audit, options.message_format, config);
+        }
+        Some(Command::Licenses(ref licenses)) => {
+            return licenses::licenses(licenses, options.policy.as_deref(), config);
+        }
+        Some(Command::Sbom(ref sbom)) => {
+            return sbom::sbom(sbom, config).with_context(|| "failed to write SBOM");
+        }
+        Some(Command::Bundle(ref bundle)) => {
+            return bundle::bundle(bundle, config).with_context(|| "failed to bundle registry");
+        }
+        Some(Command::Unbundle(ref bundle)) => {
+            bundle::unbundle(bundle, config).with_context(|| "failed to unbundle registry")?;
+            let mut sources = BTreeMap::new();
+            sources.insert(id, path.canonicalize()?);
+            sources
+        }
+        Some(Command::Export(ref export)) => {
+            export::export(export, config).with_context(|| "failed to export registry")?;
+            if let Some(ref key) = options.sign_key {
+                sign::sign(Path::new(&export.dst), Path::new(key))
+                    .with_context(|| "failed to sign the export")?;
+            }
+            return Ok(());
+        }
+        Some(Command::Verify(ref verify)) => {
+            return sign::verify(verify, options.message_format, config);
+        }
+        Some(Command::Info(ref info)) => {
+            return provenance::info(info, options.message_format, config);
+        }
+        Some(Command::Snapshot(ref snapshot)) => {
+            snapshot::snapshot(snapshot, options.store.as_deref())?;
+            match snapshot.command {
+                SnapshotCommand::Restore(ref restore) if !restore.dry_run => {
+                    let mut sources = BTreeMap::new();
+                    sources.insert(id, path.canonicalize()?);
+                    sources
+                }
+                _ => return Ok(()),
+            }
+        }
+        Some(Command::Keygen(_)) => unreachable!(),
+        Some(Command::Import(ref import)) => {
+            import::import(import, &id, config).with_context(|| "failed to import crates")?
+        }
+        Some(Command::PublishLocal(ref publish)) => publish::publish_local(publish, &id, config)
+            .with_context(|| "failed to publish crates")?,
+        None => {
+            let lockfile = match options.sync {
+                Some(ref file) => file,
+                None => return Ok(()),
+            };
+            sync(Path::new(lockfile), &path, &id, &options, config)
+                .with_context(|| "failed to sync")?
+        }
+    };
+
+    if dry_run {
+        return Ok(());
+    }
+    finish(&sources, &options)?;
+    if matches!(options.command, None | Some(Command::Add(_))) {
+        summary.print(options.message_format, config)?;
+    }
+    if options.message_format == MessageFormat::Json {
+        let sources = sources
+            .iter()
+            .map(|(id, root)| serde_json::json!({ "source": id.url().as_str(), "path": root }))
+            .collect::<Vec<_>>();
+        message::emit(serde_json::json!({ "reason": "finished", "sources": sources }));
+        return Ok(());
+    }
+    if options.index_format == Some(IndexFormat::Git) {
+        for root in sources.values().collect::<BTreeSet<_>>() {
+            println!(
+                "committed the index to the git repository at `{}`",
+                config.cwd().join(index::dir(root)).display()
+            );
+        }
+    }
+    if options.dl.is_some() {
+        println!(
+            "serve `{}` over HTTP and point a sparse registry at it",
+            config.cwd().join(&index).display()
+        );
+        return Ok(());
+    }
+
+    for root in sources.values().filter(|root| root.join("crates").is_dir()) {
+        config.shell().warn(format!(
+            "`{}` has sharded crate files, which `local-registry` sources can't \
+             read, serve it with a `dl` template such as \
+             `.../crates/{{lowerprefix}}/{{crate}}-{{version}}.crate`",
+            root.display()
+        ))?;
+    }
+
+    let mut snippet = String::new();
+    let mut locals = BTreeMap::new();
+    for (source_id, root) in sources.iter() {
+        let name = match source_id.alt_registry_key() {
+            _ if source_id.is_crates_io() => "crates-io".to_string(),
+            Some(key) => key.to_string(),
+            None => source_id.url().host_str().unwrap().replace('.', "-"),
+        };
+        let local = if *root == sources[&id] {
+            "local-registry".to_string()
+        } else {
+            format!("local-registry-{}", name)
+        };
+        snippet.push_str(&format!(
+            "    [source.{}]\n    registry = '{}'\n    replace-with = '{}'\n\n",
+            name,
+            source_id.url(),
+            local
+        ));
+        locals.insert(local, config.cwd().join(root));
+    }
+    for (local, root) in locals.iter() {
+        snippet.push_str(&format!(
+            "    [source.{}]\n    local-registry = '{}'\n\n",
+            local,
+            root.display()
+        ));
+    }
+
+    println!("add this to your .cargo/config somewhere:\n\n{}", snippet);
+
+    Ok(())
+}
+
+fn sync(
+    lockfile: &Path,
+    local_dst: &Path,
+    registry_id: &SourceId,
+    options: &Options,
+    config: &GlobalContext,
+) -> CargoResult<BTreeMap<SourceId, PathBuf>> {
+    let manifest = match options.manifest_path {
+        Some(ref path) => PathBuf::from(path),
+        None => lockfile.parent().unwrap().join("Cargo.toml"),
+    };
+    let manifest = env::current_dir().unwrap().join(&manifest);
+    let ws = Workspace::new(&manifest, config)?;
+
+    // Cargo always resolves against the lock file at the root of the
+    // workspace, so make sure that's the one we were asked to sync with.
+    let ws_lockfile = ws.root().join("Cargo.lock");
+    let lockfile = env::current_dir().unwrap().join(lockfile);
+    if ws_lockfile.canonicalize().ok() != lockfile.canonicalize().ok() {
+        anyhow::bail!(
+            "`{}` is not the lock file of the workspace at `{}`, expected `{}`",
+            lockfile.display(),
+            ws.root().display(),
+            ws_lockfile.display()
+        );
+    }
+    let (packages, resolve) =
+        cargo::ops::resolve_ws(&ws).with_context(|| "failed to load pkg lockfile")?;
+    download(&packages, &resolve, options, options.git)?;
+
+    vendor(
+        &packages,
+        &resolve,
+        local_dst,
+        registry_id,
+        options,
+        options.git,
+        options.no_delete,
+        config,
+    )
+}
+
+/// Writes what goes along with the crate files and index entries once they
+/// are in place: sidecars, the signed manifest, and the git index commit.
+fn finish(sources: &BTreeMap<SourceId, PathBuf>, options: &Options) -> CargoResult<()> {
+    let roots = sources.values().collect::<BTreeSet<_>>();
+    for root in roots.iter() {
+        write_sidecars(root, options.sha256_files)?;
+    }
+    if let Some(ref key) = options.sign_key {
+        for root in roots.iter() {
+            sign::sign(root, Path::new(key)).with_context(|| "failed to sign the registry")?;
+        }
+    }
+    if options.index_format == Some(IndexFormat::Git) {
+        for root in roots.iter() {
+            commit_index(root, options.dl.is_none())?;
+        }
+    }
+    Ok(())
+}
+
+/// Downloads every package of `resolve`, after telling the progress
+/// reporter how many `vendor` will add.
+fn download(
+    packages: &PackageSet<'_>,
+    resolve: &Resolve,
+    options: &Options,
+    git: bool,
+) -> CargoResult<()> {
+    let crates = resolve
+        .iter()
+        .filter(|id| id.source_id().is_registry() || (git && id.source_id().is_git()))
+        .count();
+    report(options, &Event::Downloading { crates });
+    packages.get_many(resolve.iter())?;
+    Ok(())
+}
+
+fn report(options: &Options, event: &Event<'_>) {
+    if let Some(ref progress) = options.progress {
+        progress.report(event);
+    }
+}
+
+/// Checks the packages in `resolve` against the policy and the maximum crate
+/// size, if any, before anything is written.
+fn check_crates(
+    packages: &PackageSet<'_>,
+    resolve: &Resolve,
+    registry_id: &SourceId,
+    options: &Options,
+    git: bool,
+    config: &GlobalContext,
+) -> CargoResult<()> {
+    if let Some(ref policy) = options.policy {
+        check_policy(
+            packages,
+            resolve,
+            &policy::Policy::load(Path::new(policy))?,
+            git,
+        )?;
+    }
+    if let Some(max) = options.max_crate_size {
+        check_crate_sizes(packages, resolve, registry_id, max, config)?;
+    }
+    Ok(())
+}
+
+/// Copies every registry (and with `git`, git) package in `resolve` into the
+/// local registry at `local_dst` and writes their index entries. Unless
+/// `no_delete` is set, anything else in the registry is removed afterwards.
+#[allow(clippy::too_many_arguments)]
+fn vendor(
+    packages: &PackageSet<'_>,
+    resolve: &Resolve,
+    local_dst: &Path,
+    registry_id: &SourceId,
+    options: &Options,
+    git: bool,
+    no_delete: bool,
+    config: &GlobalContext,
+) -> CargoResult<BTreeMap<SourceId, PathBuf>> {
+    let retention = Retention::parse(&options.keep_versions)?;
+    check_crates(packages, resolve, registry_id, options, git, config)?;
+    let canonical_local_dst = local_dst.canonicalize().unwrap_or(local_dst.to_path_buf());
+
+    let dl_manifest = match options.dl_manifest {
+        Some(ref file) => Some(read_manifest(Path::new(file))?),
+        None => None,
+    };
+
+    let mut sources = BTreeMap::new();
+    sources.insert(*registry_id, canonical_local_dst.clone());
+    let mut added_crates = HashSet::new();
+    let mut added_index = HashSet::new();
+    let mut locked_versions = HashMap::new();
+    let mut added_provenance = HashMap::new();
+    let command = match options.command {
+        Some(Command::Add(_)) => "add",
+        _ => "sync",
+    };
+    for id in resolve.iter() {
+        if id.source_id().is_git() {
+            if !git {
+                continue;
+            }
+        } else if !id.source_id().is_registry() {
+            continue;
+        }
+
+        let source_id = download_source(id, registry_id);
+        let local_dst = if source_id != *registry_id && options.split_sources {
+            canonical_local_dst.join(cache_name(&source_id))
+        } else {
+            canonical_local_dst.clone()
+        };
+        if id.source_id().is_registry() {
+            sources.insert(source_id, local_dst.clone());
+        }
+
+        let pkg = packages
+            .get_one(id)
+            .with_context(|| "failed to fetch package")?;
+        let name = id.name().to_string();
+        let version = id.version().to_string();
+        report(
+            options,
+            &Event::Writing {
+                name: &name,
+                version: &version,
+            },
+        );
+        let filename = format!("{}-{}.crate", id.name(), id.version());
+        if options.shard_crates && !local_dst.join("crates").is_dir() {
+            shard_crates(&local_dst)?;
+        }
+        let dst = crate_path(&local_dst, &id.name(), &id.version().to_string());
+        fs::create_dir_all(dst.parent().unwrap())?;
+        if id.source_id().is_registry() {
+            match options.dl {
+                // The crate file lives elsewhere, so only make sure that what's
+                // hosted there matches what we're about to put in the index.
+                Some(ref dl) => {
+                    let cksum = resolve
+                        .checksums()
+                        .get(&id)
+                        .cloned()
+                        .flatten()
+                        .unwrap_or_default();
+                    let actual = match dl_manifest {
+                        Some(ref manifest) => {
+                            manifest.get(&filename).cloned().ok_or_else(|| {
+                                anyhow::format_err!(
+                                    "`{}` is missing from the dl manifest",
+                                    filename
+                                )
+                            })?
+                        }
+                        None => {
+                            let url = dl_url(dl, &id.name(), &id.version().to_string(), &cksum);
+                            remote_checksum(&url, config)?
+                        }
+                    };
+                    if actual != cksum {
+                        return Err(Error::Integrity(format!(
+                            "checksum mismatch for externally hosted `{}`: expected {}, found {}",
+                            filename, cksum, actual
+                        ))
+                        .into());
+                    }
+                }
+                None => {
+                    let src = config
+                        .registry_cache_path()
+                        .join(cache_name(&source_id))
+                        .join(&filename)
+                        .into_path_unlocked();
+                    replace_file(&dst)?;
+                    fs::copy(&src, &dst).with_context(|| {
+                        format!("failed to copy `{}` to `{}`", src.display(), dst.display())
+                    })?;
+                    if let Some(ref store) = options.store {
+                        link_into_store(&dst, Path::new(store))?;
+                    }
+                    added_crates.insert(dst);
+                }
+            }
+        } else {
+            replace_file(&dst)?;
+            {
+                let file = File::create(&dst).unwrap();
+                let gz = GzEncoder::new(file, flate2::Compression::best());
+                let mut ar = Builder::new(gz);
+                ar.mode(tar::HeaderMode::Deterministic);
+                build_ar(&mut ar, pkg, config);
+            }
+            if let Some(ref store) = options.store {
+                link_into_store(&dst, Path::new(store))?;
+            }
+            added_crates.insert(dst);
+        }
+
+        let dst = index::path(&local_dst, &id.name());
+        fs::create_dir_all(dst.parent().unwrap())?;
+        let cksum = resolve.checksums().get(&id).cloned().flatten();
+        let entry = registry_pkg(pkg, cksum.unwrap_or_default());
+        let line = serde_json::to_string(&entry).unwrap();
+        let origin = if id.source_id().is_registry() {
+            source_id.as_url().to_string()
+        } else {
+            id.source_id().as_url().to_string()
+        };
+        added_provenance
+            .entry(local_dst.clone())
+            .or_insert_with(Vec::new)
+            .push(provenance::Provenance::new(
+                &entry.name,
+                &entry.vers,
+                &entry.cksum,
+                origin,
+                command,
+            ));
+
+        let prev = if no_delete || retention.is_some() || added_index.contains(&dst) {
+            read(&dst).unwrap_or_default()
+        } else {
+            // If cleaning old entries (no_delete is not set), don't read the file unless we wrote
+            // it in one of the previous iterations.
+            String::new()
+        };
+        let mut prev_entries = Vec::new();
+        for prev_line in prev.lines() {
+            if index::parse_line(&dst, prev_line)?.vers != id.version().to_string() {
+                prev_entries.push(prev_line);
+            }
+        }
+        prev_entries.push(&line);
+        prev_entries.sort();
+        let new_contents = prev_entries.join("\n");
+
+        File::create(&dst).and_then(|mut f| f.write_all(new_contents.as_bytes()))?;
+        report(options, &Event::IndexUpdated { path: &dst });
+        locked_versions
+            .entry(dst.clone())
+            .or_insert_with(HashSet::new)
+            .insert(id.version().to_string());
+        added_index.insert(dst);
+
+        let bytes = match options.dl {
+            Some(_) => 0,
+            None => fs::metadata(crate_path(&local_dst, &name, &version))?.len(),
+        };
+        report(
+            options,
+            &Event::Written {
+                name: &name,
+                version: &version,
+                bytes,
+                packaged: id.source_id().is_git(),
+            },
+        );
+    }
+
+    let roots = sources.values().collect::<BTreeSet<_>>();
+
+    if let Some(ref dl) = options.dl {
+        for root in roots.iter() {
+            let dst = index::dir(root).join("config.json");
+            let config = serde_json::to_string(&RegistryConfig { dl, api: None }).unwrap();
+            File::create(&dst).and_then(|mut f| f.write_all(config.as_bytes()))?;
+            added_index.insert(dst);
+        }
+    }
+
+    if let Some(ref retention) = retention {
+        for root in roots.iter() {
+            for path in index_files(&index::dir(root))? {
+                let locked = locked_versions.remove(&path).unwrap_or_default();
+                let kept = retain_versions(&path, &locked, retention)?;
+                if kept.is_empty() {
+                    continue;
+                }
+                for (name, vers) in kept {
+                    added_crates.insert(crate_path(root, &name, &vers));
+                }
+                added_index.insert(path);
+            }
+        }
+    }
+
+    if !no_delete {
+        for root in roots.iter() {
+            for path in crate_files(root)? {
+                if !added_crates.contains(&path) {
+                    fs::remove_file(&path)?;
+                    report(options, &Event::Deleted { path: &path });
+                    // Drop the shard directories left empty.
+                    for dir in path.ancestors().skip(1).take_while(|d| d != root) {
+                        if fs::remove_dir(dir).is_err() {
+                            break;
+                        }
+                    }
+                }
+            }
+
+            clean_index(root, &added_index)?;
+        }
+    }
+    for root in roots.iter() {
+        provenance::record(root, added_provenance.remove(*root).unwrap_or_default())?;
+    }
+    Ok(sources)
+}
+
+/// Fills in the registry of `command` with `default` when it isn't given.
+/// With a default, the first argument of `add` and `info` is taken for a
+/// crate when it names one rather than a directory, so `add serde` works.
+fn default_registry(command: &mut Command, default: Option<&str>) -> CargoResult<()> {
+    let is_crate =
+        |arg: &str| default.is_some() && !Path::new(arg).is_dir() && add::parse_spec(arg).is_ok();
+    let path = match command {
+        Command::Add(add) => {
+            if is_crate(&add.path) {
+                add.crates.insert(0, std::mem::take(&mut add.path));
+            }
+            &mut add.path
+        }
+        Command::Info(info) => {
+            if info.spec.is_empty() && is_crate(&info.path) {
+                info.spec = std::mem::take(&mut info.path);
+            }
+            if info.spec.is_empty() {
+                anyhow::bail!("no crate given");
+            }
+            &mut info.path
+        }
+        Command::Audit(audit) => &mut audit.path,
+        Command::Sbom(sbom) => &mut sbom.path,
+        Command::Licenses(licenses) => &mut licenses.path,
+        Command::Verify(verify) => &mut verify.path,
+        Command::PublishLocal(publish) => &mut publish.path,
+        Command::Snapshot(snapshot) => match snapshot.command {
+            SnapshotCommand::Create(ref mut args) | SnapshotCommand::Show(ref mut args) => {
+                snapshot_name(&mut args.path, &mut args.name, default)?
+            }
+            SnapshotCommand::Restore(ref mut args) => {
+                snapshot_name(&mut args.path, &mut args.name, default)?
+            }
+            SnapshotCommand::List(ref mut args) => &mut args.path,
+        },
+        _ => return Ok(()),
+    };
+    if path.is_empty() {
+        match default {
+            Some(default) => *path = default.to_string(),
+            None => anyhow::bail!("no registry path given, and none configured"),
+        }
+    }
+    Ok(())
+}
+
+/// Takes the lone argument of a `snapshot` subcommand for the name of the
+/// snapshot when there is a default registry, and returns its path.
+fn snapshot_name<'a>(
+    path: &'a mut String,
+    name: &mut String,
+    default: Option<&str>,
+) -> CargoResult<&'a mut String> {
+    if name.is_empty() && default.is_some() {
+        *name = std::mem::take(path);
+    }
+    if name.is_empty() {
+        anyhow::bail!("no snapshot given");
+    }
+    Ok(path)
+}
+
+/// Creates the index of the registry at `root`, laid out as `--layout` says
+/// unless the registry already has one. Registries with their index at the
+/// root are told apart by their `config.json`, so that is written right away.
+fn create_index(root: &Path, options: &Options) -> CargoResult<()> {
+    let index = index::dir(root);
+    if index.is_dir() {
+        return Ok(());
+    }
+    match (options.layout.unwrap_or_default(), &options.dl) {
+        (Layout::Index, _) => fs::create_dir_all(&index)
+            .with_context(|| format!("failed to create index: `{}`", index.display())),
+        (Layout::Root, Some(dl)) => {
+            fs::create_dir_all(root)
+                .with_context(|| format!("failed to create `{}`", root.display()))?;
+            let config = serde_json::to_string(&RegistryConfig { dl, api: None }).unwrap();
+            File::create(root.join("config.json"))
+                .and_then(|mut f| f.write_all(config.as_bytes()))?;
+            Ok(())
+        }
+        (Layout::Root, None) => anyhow::bail!(
+            "`--layout root` needs `--dl`, `local-registry` sources only read an `index` directory"
+        ),
+    }
+}
+
+/// Turns the index of the registry at `root` into a git repository, unless it
+/// already is one, and commits its current state. Without an externally
+/// hosted `dl`, the `config.json` cargo needs points at the crate files next
+/// to the index.
+fn commit_index(root: &Path, write_config: bool) -> CargoResult<()> {
+    let index = index::dir(root);
+    if write_config {
+        let shards = if root.join("crates").is_dir() {
+            "crates/{lowerprefix}/"
+        } else {
+            ""
+        };
+        let dl = format!(
+            "{}{}{{crate}}-{{version}}.crate",
+            Url::from_directory_path(root.canonicalize()?)
+                .map_err(|()| anyhow::format_err!("invalid path `{}`", root.display()))?,
+            shards
+        );
+        let config = serde_json::to_string(&RegistryConfig { dl: &dl, api: None }).unwrap();
+        File::create(index.join("config.json")).and_then(|mut f| f.write_all(config.as_bytes()))?;
+    }
+
+    let repo = match git2::Repository::open(&index) {
+        Ok(repo) => repo,
+        Err(_) => git2::Repository::init(&index)?,
+    };
+    let mut git_index = repo.index()?;
+    git_index.add_all(["*"], git2::IndexAddOption::DEFAULT, None)?;
+    git_index.update_all(["*"], None)?;
+    git_index.write()?;
+    let tree = repo.find_tree(git_index.write_tree()?)?;
+    let parent = repo.head().ok().and_then(|head| head.peel_to_commit().ok());
+    if parent.as_ref().map(|p| p.tree_id()) == Some(tree.id()) {
+        return Ok(());
+    }
+    let sig = repo.signature().or_else(|_| {
+        git2::Signature::now("cargo-local-registry", "cargo-local-registry@localhost")
+    })?;
+    repo.commit(
+        Some("HEAD"),
+        &sig,
+        &sig,
+        "Update index",
+        &tree,
+        &parent.iter().collect::<Vec<_>>(),
+    )?;
+    Ok(())
+}
+
+/// Checks everything `vendor` is about to add against `policy`, before
+/// anything gets written.
+fn check_policy(
+    packages: &PackageSet<'_>,
+    resolve: &Resolve,
+    policy: &policy::Policy,
+    git: bool,
+) -> CargoResult<()> {
+    let mut violations = Vec::new();
+    for id in resolve.iter() {
+        if !(id.source_id().is_registry() || (git && id.source_id().is_git())) {
+            continue;
+        }
+        let pkg = packages.get_one(id)?;
+        let license = pkg.manifest().metadata().license.as_deref();
+        let checked = policy
+            .check_crate(&id.name(), id.version())
+            .and_then(|()| policy.check_license(license));
+        if let Err(reason) = checked {
+            violations.push(format!("{} {}: {}", id.name(), id.version(), reason));
+        }
+    }
+    if !violations.is_empty() {
+        return Err(Error::Policy(format!(
+            "{} crates are disallowed by the policy:\n  {}",
+            violations.len(),
+            violations.join("\n  ")
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+/// Fails if any registry crate `vendor` is about to add is larger than `max`
+/// bytes, before anything gets written.
+fn check_crate_sizes(
+    packages: &PackageSet<'_>,
+    resolve: &Resolve,
+    registry_id: &SourceId,
+    max: u64,
+    config: &GlobalContext,
+) -> CargoResult<()> {
+    let mut too_large = Vec::new();
+    for id in resolve.iter().filter(|id| id.source_id().is_registry()) {
+        packages.get_one(id)?;
+        let file = config
+            .registry_cache_path()
+            .join(cache_name(&download_source(id, registry_id)))
+            .join(format!("{}-{}.crate", id.name(), id.version()))
+            .into_path_unlocked();
+        let size = fs::metadata(&file)
+            .with_context(|| format!("failed to read `{}`", file.display()))?
+            .len();
+        if size > max {
+            too_large.push(format!("{} {} ({} bytes)", id.name(), id.version(), size));
+        }
+    }
+    if !too_large.is_empty() {
+        return Err(Error::Policy(format!(
+            "{} crates are larger than the maximum of {} bytes:\n  {}",
+            too_large.len(),
+            max,
+            too_large.join("\n  ")
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+/// Returns the registry `id` was downloaded from. Crates from crates.io come
+/// from `registry_id`, which may be the sparse index, while everything else
+/// comes from the registry recorded in the lock file.
+fn download_source(id: PackageId, registry_id: &SourceId) -> SourceId {
+    if id.source_id().is_registry() && !id.source_id().is_crates_io() {
+        id.source_id()
+    } else {
+        *registry_id
+    }
+}
+
+/// Parses a size like `20MB`, `512KiB` or a plain number of bytes.
+fn parse_size(s: &str) -> Result<u64, String> {
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let number = number
+        .parse::<u64>()
+        .map_err(|_| format!("invalid size `{}`", s))?;
+    let multiplier = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "kb" => 1_000,
+        "mb" => 1_000_000,
+        "gb" => 1_000_000_000,
+        "kib" => 1 << 10,
+        "mib" => 1 << 20,
+        "gib" => 1 << 30,
+        _ => return Err(format!("invalid size unit in `{}`", s)),
+    };
+    number
+        .checked_mul(multiplier)
+        .ok_or_else(|| format!("size `{}` is too large", s))
+}
+
+/// Name of the directory Cargo uses for a registry's caches, e.g.
+/// `index.crates.io-6f17d22bba15001f`.
+fn cache_name(id: &SourceId) -> String {
+    let hash = cargo::util::hex::short_hash(id);
+    let ident = id.url().host().unwrap().to_string();
+    format!("{}-{}", ident, hash)
+}
+
+/// Trims the index file at `path` down to the versions in `locked` plus the
+/// newest of the remaining ones, as many as `retention` allows, returning the
+/// names and versions of the entries that were kept. The file is removed when
+/// none are.
+fn retain_versions(
+    path: &Path,
+    locked: &HashSet<String>,
+    retention: &Retention,
+) -> CargoResult<Vec<(String, String)>> {
+    let contents = read(path)?;
+    let mut entries = contents
+        .lines()
+        .map(|line| {
+            let pkg = index::parse_line(path, line)?;
+            let vers = pkg
+                .vers
+                .parse::<semver::Version>()
+                .with_context(|| format!("invalid version in `{}`", path.display()))?;
+            Ok((vers, pkg, line))
+        })
+        .collect::<CargoResult<Vec<_>>>()?;
+    entries.sort_by(|a, b| b.0.cmp(&a.0));
+
+    let mut extra = match entries.first() {
+        Some((_, pkg, _)) => match retention.versions_for(&pkg.name) {
+            Some(n) => n.saturating_sub(locked.len()),
+            None => usize::MAX,
+        },
+        None => 0,
+    };
+    let mut kept = Vec::new();
+    let mut lines = Vec::new();
+    for (_, pkg, line) in entries {
+        if !locked.contains(&pkg.vers) {
+            if extra == 0 {
+                continue;
+            }
+            extra -= 1;
+        }
+        lines.push(line);
+        kept.push((pkg.name, pkg.vers));
+    }
+    lines.sort();
+
+    // Nothing is left of the crate, so drop its index file rather than leave
+    // an empty one behind.
+    if lines.is_empty() {
+        fs::remove_file(path)?;
+        return Ok(kept);
+    }
+    File::create(path).and_then(|mut f| f.write_all(lines.join("\n").as_bytes()))?;
+    Ok(kept)
+}
+
+/// Lists all the index files below `path`, skipping `config.json`.
+fn index_files(index: &Path) -> CargoResult<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    if !index.is_dir() {
+        return Ok(files);
+    }
+    for entry in index.read_dir()? {
+        let path = entry?.path();
+        if path.is_dir() && is_shard(&path) {
+            files.extend(walk(&path)?);
+        }
+    }
+    Ok(files)
+}
+
+/// Tells whether `path`, at the top of an index, is a directory index files
+/// are sharded in: `1`, `2`, `3` or the first two letters of longer names.
+fn is_shard(path: &Path) -> bool {
+    match path.file_name().and_then(|n| n.to_str()) {
+        Some(name) => matches!(name, "1" | "2" | "3") || (name.len() == 2 && !is_hidden(path)),
+        None => false,
+    }
+}
+
+/// Lists the files under `dir`, recursively.
+fn walk(dir: &Path) -> CargoResult<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in dir.read_dir()? {
+        let path = entry?.path();
+        if path.is_dir() {
+            files.extend(walk(&path)?);
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(files)
+}
+
+/// Removes the index files of the registry at `root` not in `keep`, and the
+/// shard directories left empty. At the top of an `index/` directory, only
+/// `config.json` may be kept, while the top of a registry laid out like the
+/// crates.io index has plenty of other files.
+fn clean_index(root: &Path, keep: &HashSet<PathBuf>) -> CargoResult<()> {
+    let index = index::dir(root);
+    if !index.is_dir() {
+        return Ok(());
+    }
+    for entry in index.read_dir()? {
+        let path = entry?.path();
+        if is_hidden(&path) {
+            // Something like the `.git` directory of a git index.
+        } else if path.is_dir() && is_shard(&path) {
+            scan_delete(&path, 2, keep)?;
+        } else if path.is_file() && index != root && !keep.contains(&path) {
+            fs::remove_file(&path)?;
+        }
+    }
+    Ok(())
+}
+
+fn scan_delete(path: &Path, depth: usize, keep: &HashSet<PathBuf>) -> CargoResult<()> {
+    if path.is_file() && !keep.contains(path) {
+        fs::remove_file(path)?;
+    } else if path.is_dir() && depth > 0 {
+        for entry in (path.read_dir()?).flatten() {
+            scan_delete(&entry.path(), depth - 1, keep)?;
+        }
+        if path.read_dir()?.next().is_none() {
+            fs::remove_dir(path)?;
+        }
+    }
+    Ok(())
+}
+
+fn is_hidden(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|n| n.to_str())
+        .is_some_and(|n| n.starts_with('.'))
+}
+
+/// Adds `pkg` to the index of the registry at `root`, replacing any entry
+/// for the same version.
+fn write_index_entry(root: &Path, pkg: &RegistryPackage) -> CargoResult<()> {
+    let path = index::path(root, &pkg.name);
+    let mut entries = index::read_file(&path)?;
+    index::merge(&mut entries, pkg.clone());
+    index::write_file(&path, &entries)?;
+    Ok(())
+}
+
+/// Returns where the crate file of `name` at `version` lives in the registry
+/// at `root`: next to the index, or in sharded registries (those with a
+/// `crates` directory) under `crates/`, sharded like the index.
+fn crate_path(root: &Path, name: &str, version: &str) -> PathBuf {
+    let filename = format!("{}-{}.crate", name, version);
+    let crates = root.join("crates");
+    if crates.is_dir() {
+        crates
+            .join(index::prefix(&name.to_lowercase()))
+            .join(filename)
+    } else {
+        root.join(filename)
+    }
+}
+
+/// Lists the crate files of the registry at `root`, wherever they are.
+fn crate_files(root: &Path) -> CargoResult<Vec<PathBuf>> {
+    files_ending_with(root, ".crate")
+}
+
+/// Lists the `.crate.sha256` sidecars of the registry at `root`.
+fn sidecar_files(root: &Path) -> CargoResult<Vec<PathBuf>> {
+    files_ending_with(root, ".crate.sha256")
+}
+
+fn files_ending_with(root: &Path, suffix: &str) -> CargoResult<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    if let Ok(entries) = root.read_dir() {
+        for entry in entries {
+            files.push(entry?.path());
+        }
+    }
+    if root.join("crates").is_dir() {
+        files.extend(walk(&root.join("crates"))?);
+    }
+    files.retain(|path| {
+        path.is_file()
+            && path
+                .file_name()
+                .is_some_and(|n| n.to_string_lossy().ends_with(suffix))
+    });
+    files.sort();
+    Ok(files)
+}
+
+/// Writes a `NAME-VERSION.crate.sha256` next to every crate file of the
+/// registry at `root`, in the format of `sha256sum`, and removes those of
+/// crates that are gone. Registries keep their sidecars once they have them,
+/// so this does nothing unless `force` is set or some are already there.
+fn write_sidecars(root: &Path, force: bool) -> CargoResult<()> {
+    let sidecars = sidecar_files(root)?;
+    if !force && sidecars.is_empty() {
+        return Ok(());
+    }
+    let crates = crate_files(root)?;
+    for file in crates.iter() {
+        let mut sha = Sha256::new();
+        let line = format!(
+            "{}  {}\n",
+            sha.update_path(file)?.finish_hex(),
+            file.file_name().unwrap().to_string_lossy()
+        );
+        let sidecar = sidecar_path(file);
+        if read(&sidecar).ok().as_deref() != Some(line.as_str()) {
+            File::create(&sidecar).and_then(|mut f| f.write_all(line.as_bytes()))?;
+        }
+    }
+    for sidecar in sidecars {
+        if !crates.contains(&sidecar.with_extension("")) {
+            fs::remove_file(&sidecar)?;
+        }
+    }
+    Ok(())
+}
+
+fn sidecar_path(crate_file: &Path) -> PathBuf {
+    let mut path = crate_file.as_os_str().to_owned();
+    path.push(".sha256");
+    PathBuf::from(path)
+}
+
+/// Removes `path` before it gets written again, as it may be a hard link to a
+/// file of the store that mustn't change.
+fn replace_file(path: &Path) -> CargoResult<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+/// Replaces `file` with a hard link to the copy of it in `store`, named after
+/// its sha256, moving it there first if the store doesn't have it yet.
+fn link_into_store(file: &Path, store: &Path) -> CargoResult<()> {
+    let mut sha = Sha256::new();
+    let hash = sha.update_path(file)?.finish_hex();
+    let stored = store.join(&hash[..2]).join(&hash);
+    fs::create_dir_all(stored.parent().unwrap())?;
+    if !stored.exists() {
+        fs::hard_link(file, &stored)
+    } else {
+        fs::remove_file(file).and_then(|()| fs::hard_link(&stored, file))
+    }
+    .with_context(|| {
+        format!(
+            "failed to link `{}` to `{}`, the store must be on the same filesystem",
+            file.display(),
+            stored.display()
+        )
+    })?;
+    Ok(())
+}
+
+/// Checks the registry at `root` is laid out the way Cargo expects, returning
+/// every problem found: index files that aren't where the name of their
+/// entries puts them, entries that don't parse, and crate files that are
+/// missing, don't match their checksum, or aren't in the index.
+fn check_layout(root: &Path) -> CargoResult<Vec<String>> {
+    let index = index::dir(root);
+    if !index.is_dir() {
+        anyhow::bail!("`{}` isn't a local registry", root.display());
+    }
+    // Crate files hosted elsewhere can't be checked.
+    let hosted = match read(&index.join("config.json")) {
+        Ok(contents) => serde_json::from_str::<serde_json::Value>(&contents)
+            .ok()
+            .and_then(|config| config["dl"].as_str().map(|dl| !dl.starts_with("file:")))
+            .unwrap_or(false),
+        Err(_) => false,
+    };
+
+    let mut problems = Vec::new();
+    let mut referenced = HashSet::new();
+    for path in index_files(&index)? {
+        let relative = path.strip_prefix(root).unwrap().display();
+        for (i, line) in read(&path)?.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let pkg: RegistryPackage = match serde_json::from_str(line) {
+                Ok(pkg) => pkg,
+                Err(e) => {
+                    problems.push(format!("{}:{}: invalid entry: {}", relative, i + 1, e));
+                    continue;
+                }
+            };
+            if index::path(root, &pkg.name) != path {
+                problems.push(format!(
+                    "{}:{}: `{}` belongs in `{}`",
+                    relative,
+                    i + 1,
+                    pkg.name,
+                    index::path(root, &pkg.name)
+                        .strip_prefix(root)
+                        .unwrap()
+                        .display()
+                ));
+            }
+            if hosted {
+                continue;
+            }
+            let file = crate_path(root, &pkg.name, &pkg.vers);
+            let name = format!("{}-{}.crate", pkg.name, pkg.vers);
+            if !file.is_file() {
+                problems.push(format!("{}: missing", name));
+            } else if !pkg.cksum.is_empty()
+                && Sha256::new().update_path(&file)?.finish_hex() != pkg.cksum
+            {
+                problems.push(format!("{}: checksum doesn't match the index", name));
+            }
+            referenced.insert(file);
+        }
+    }
+    if !hosted {
+        for file in crate_files(root)? {
+            if !referenced.contains(&file) {
+                problems.push(format!(
+                    "{}: not in the index",
+                    file.strip_prefix(root).unwrap().display()
+                ));
+            }
+        }
+    }
+    Ok(problems)
+}
+
+/// Reads the `Cargo.toml` packaged in the crate file of `name` at `version`
+/// in the registry at `root`.
+fn crate_manifest(root: &Path, name: &str, version: &str) -> CargoResult<toml::Table> {
+    let path = crate_path(root, name, version);
+    let file = File::open(&path).with_context(|| format!("failed to open `{}`", path.display()))?;
+    let manifest = Path::new(&format!("{}-{}", name, version)).join("Cargo.toml");
+    let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(file));
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if *entry.path()? == *manifest {
+            let mut contents = String::new();
+            entry.read_to_string(&mut contents)?;
+            return toml::from_str(&contents)
+                .with_context(|| format!("invalid manifest in `{}`", path.display()));
+        }
+    }
+    anyhow::bail!("`{}` has no manifest", path.display())
+}
+
+/// The license fields of a crate's manifest.
+#[derive(Default)]
+struct License {
+    license: Option<String>,
+    file: Option<String>,
+}
+
+/// Reads the license fields of the crate `name` at `version` in the registry
+/// at `root`. When its manifest can't be read, this warns and returns `None`,
+/// leaving the license unknown, for a policy to reject.
+fn crate_license(
+    root: &Path,
+    name: &str,
+    version: &str,
+    config: &GlobalContext,
+) -> CargoResult<Option<License>> {
+    let manifest = match crate_manifest(root, name, version) {
+        Ok(manifest) => manifest,
+        Err(e) => {
+            config.shell().warn(format!(
+                "no license information for `{} {}`: {:#}",
+                name, version, e
+            ))?;
+            return Ok(None);
+        }
+    };
+    let field = |key| {
+        manifest
+            .get("package")
+            .and_then(|p| p.get(key))
+            .and_then(|v| v.as_str())
+            .map(|s| s.to_string())
+    };
+    Ok(Some(License {
+        license: field("license"),
+        file: field("license-file"),
+    }))
+}
+
+/// Moves the crate files of the registry at `root` under `crates/`, making it
+/// a sharded registry.
+fn shard_crates(root: &Path) -> CargoResult<()> {
+    fs::create_dir_all(root.join("crates"))?;
+    for path in index_files(&index::dir(root))? {
+        for line in read(&path)?.lines() {
+            let pkg = index::parse_line(&path, line)?;
+            let flat = root.join(format!("{}-{}.crate", pkg.name, pkg.vers));
+            if flat.is_file() {
+                let dst = crate_path(root, &pkg.name, &pkg.vers);
+                fs::create_dir_all(dst.parent().unwrap())?;
+                fs::rename(&flat, &dst)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn build_ar(ar: &mut Builder<GzEncoder<File>>, pkg: &Package, config: &GlobalContext) {
+    let root = pkg.root();
+    let src = PathSource::new(pkg.root(), pkg.package_id().source_id(), config);
+    for file in src.list_files(pkg).unwrap().iter() {
+        let relative = file.strip_prefix(root).unwrap();
+        let relative = relative.to_str().unwrap();
+        let mut file = File::open(file).unwrap();
+        let path = format!(
+            "{}-{}{}{}",
+            pkg.name(),
+            pkg.version(),
+            path::MAIN_SEPARATOR,
+            relative
+        );
+
+        let mut header = Header::new_ustar();
+        let metadata = file.metadata().unwrap();
+        header.set_path(&path).unwrap();
+        header.set_metadata(&metadata);
+        header.set_cksum();
+
+        ar.append(&header, &mut file).unwrap();
+    }
+}
+
+fn registry_pkg(pkg: &Package, cksum: String) -> RegistryPackage {
+    let id = pkg.package_id();
+    let mut deps = pkg
+        .dependencies()
+        .iter()
+        .map(|dep| {
+            let (name, package) = match &dep.explicit_name_in_toml() {
+                Some(explicit) => (explicit.to_string(), Some(dep.package_name().to_string())),
+                None => (dep.package_name().to_string(), None),
+            };
+
+            RegistryDependency {
+                name,
+                req: dep.version_req().to_string(),
+                features: dep.features().iter().map(|s| s.to_string()).collect(),
+                optional: dep.is_optional(),
+                default_features: dep.uses_default_features(),
+                target: dep.platform().map(|platform| match *platform {
+                    Platform::Name(ref s) => s.to_string(),
+                    Platform::Cfg(ref s) => format!("cfg({})", s),
+                }),
+                kind: match dep.kind() {
+                    DepKind::Normal => None,
+                    DepKind::Development => Some("dev".to_string()),
+                    DepKind::Build => Some("build".to_string()),
+                },
+                package,
+                extra: Default::default(),
+            }
+        })
+        .collect::<Vec<_>>();
+    // In field order, the order entries have always been written in.
+    fn key(d: &RegistryDependency) -> impl Ord + '_ {
+        (
+            &d.name,
+            &d.req,
+            &d.features,
+            d.optional,
+            d.default_features,
+            &d.target,
+            &d.kind,
+            &d.package,
+        )
+    }
+    deps.sort_by(|a, b| key(a).cmp(&key(b)));
+
+    let features = pkg
+        .summary()
+        .features()
+        .iter()
+        .map(|(k, v)| {
+            let mut v = v.iter().map(|fv| fv.to_string()).collect::<Vec<_>>();
+            v.sort();
+            (k.to_string(), v)
+        })
+        .collect();
+
+    RegistryPackage {
+        name: id.name().to_string(),
+        vers: id.version().to_string(),
+        deps,
+        features,
+        cksum,
+        yanked: Some(false),
+        extra: Default::default(),
+    }
+}
+
+/// Expands a `dl` template the same way Cargo does when downloading from a
+/// registry.
+fn dl_url(template: &str, name: &str, version: &str, cksum: &str) -> String {
+    const MARKERS: [&str; 5] = [
+        "{crate}",
+        "{version}",
+        "{prefix}",
+        "{lowerprefix}",
+        "{sha256-checksum}",
+    ];
+    if !MARKERS.iter().any(|m| template.contains(m)) {
+        return format!(
+            "{}/{}/{}/download",
+            template.trim_end_matches('/'),
+            name,
+            version
+        );
+    }
+    let prefix = index::prefix(name);
+    template
+        .replace("{crate}", name)
+        .replace("{version}", version)
+        .replace("{lowerprefix}", &prefix.to_lowercase())
+        .replace("{prefix}", &prefix)
+        .replace("{sha256-checksum}", cksum)
+}
+
+fn remote_checksum(url: &str, config: &GlobalContext) -> CargoResult<String> {
+    let mut sha = Sha256::new();
+    let mut handle = cargo::util::network::http::http_handle(config)?;
+    handle.get(true)?;
+    handle.url(url)?;
+    handle.follow_location(true)?;
+    {
+        let mut transfer = handle.transfer();
+        transfer.write_function(|data| {
+            sha.update(data);
+            Ok(data.len())
+        })?;
+        transfer
+            .perform()
+            .with_context(|| format!("failed to download `{}`", url))?;
+    }
+    let code = handle.response_code()?;
+    if code != 200 {
+        anyhow::bail!("failed to download `{}`, got {}", url, code);
+    }
+    Ok(sha.finish_hex())
+}
+
+/// Reads a manifest in the format produced by `sha256sum`, mapping file names
+/// to their checksums.
+fn read_manifest(path: &Path) -> CargoResult<HashMap<String, String>> {
+    let contents = read(path)?;
+    let mut manifest = HashMap::new();
+    for line in contents.lines().map(|l| l.trim()).filter(|l| !l.is_empty()) {
+        let (cksum, file) = line
+            .split_once(char::is_whitespace)
+            .ok_or_else(|| anyhow::format_err!("invalid manifest line: `{}`", line))?;
+        let file = file.trim_start().trim_start_matches('*');
+        let file = file.rsplit('/').next().unwrap_or(file);
+        manifest.insert(file.to_string(), cksum.to_lowercase());
+    }
+    Ok(manifest)
+}
+
+fn read(path: &Path) -> CargoResult<String> {
+    let s = (|| -> io::Result<_> {
+        let mut contents = String::new();
+        let mut f = File::open(path)?;
+        f.read_to_string(&mut contents)?;
+        Ok(contents)
+    })()
+    .with_context(|| format!("failed to read: {}", path.display()))?;
+    Ok(s)
+}
@@ -0,0 +1,65 @@
+use crate::policy::Policy;
+use crate::{crate_license, index, Error};
+use cargo::util::errors::*;
+use cargo::util::GlobalContext;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+#[derive(clap::Args)]
+pub struct LicensesOptions {
+    /// Path to the local registry, defaults to the configured one
+    #[arg(default_value_t, hide_default_value = true)]
+    pub path: String,
+}
+
+/// Prints the crates of the registry grouped by license, and with a policy,
+/// fails if any of them has a license it doesn't allow. Crates whose manifest
+/// can't be read are listed as unknown, with a warning.
+pub fn licenses(
+    options: &LicensesOptions,
+    policy: Option<&str>,
+    config: &GlobalContext,
+) -> CargoResult<()> {
+    let root = Path::new(&options.path);
+    let policy = match policy {
+        Some(policy) => Some(Policy::load(Path::new(policy))?),
+        None => None,
+    };
+    let mut entries = index::entries(root)?;
+    entries.sort_by(|a, b| (&a.name, &a.vers).cmp(&(&b.name, &b.vers)));
+
+    let mut by_license = BTreeMap::new();
+    let mut violations = Vec::new();
+    for pkg in entries.iter() {
+        let found = crate_license(root, &pkg.name, &pkg.vers, config)?;
+        let license = found.as_ref().and_then(|l| l.license.as_deref());
+        let summary = match found {
+            Some(ref found) => match (&found.license, &found.file) {
+                (Some(license), _) => license.clone(),
+                (None, Some(file)) => format!("license file `{}`", file),
+                (None, None) => "none".to_string(),
+            },
+            None => "unknown".to_string(),
+        };
+        by_license
+            .entry(summary)
+            .or_insert_with(Vec::new)
+            .push(format!("{} {}", pkg.name, pkg.vers));
+        if let Some(Err(reason)) = policy.as_ref().map(|p| p.check_license(license)) {
+            violations.push(format!("{} {}: {}", pkg.name, pkg.vers, reason));
+        }
+    }
+
+    for (license, crates) in by_license.iter() {
+        println!("{} ({}): {}", license, crates.len(), crates.join(", "));
+    }
+    if !violations.is_empty() {
+        return Err(Error::Policy(format!(
+            "{} crates are disallowed by the policy:\n  {}",
+            violations.len(),
+            violations.join("\n  ")
+        ))
+        .into());
+    }
+    Ok(())
+}
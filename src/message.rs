@@ -0,0 +1,54 @@
+//! `--message-format json`, which prints what happens as one JSON object per
+//! line on stdout instead, each with a `reason` telling what it is about.
+
+use crate::progress::{Event, Progress};
+use serde_json::{json, Value};
+
+#[derive(Clone, Copy, Default, PartialEq, clap::ValueEnum)]
+pub enum MessageFormat {
+    /// Status lines for people to read
+    #[default]
+    Human,
+    /// One JSON object per line on stdout
+    Json,
+}
+
+/// Prints the progress of a sync or add as JSON messages.
+pub struct JsonMessages;
+
+impl Progress for JsonMessages {
+    fn report(&self, event: &Event<'_>) {
+        let message = match *event {
+            Event::Downloading { crates } => json!({
+                "reason": "downloading",
+                "crates": crates,
+            }),
+            Event::Writing { .. } => return,
+            Event::Written {
+                name,
+                version,
+                bytes,
+                packaged,
+            } => json!({
+                "reason": "crate-added",
+                "name": name,
+                "version": version,
+                "bytes": bytes,
+                "packaged": packaged,
+            }),
+            Event::IndexUpdated { path } => json!({
+                "reason": "index-updated",
+                "path": path,
+            }),
+            Event::Deleted { path } => json!({
+                "reason": "crate-deleted",
+                "path": path,
+            }),
+        };
+        emit(message);
+    }
+}
+
+pub fn emit(message: Value) {
+    println!("{}", message);
+}
@@ -0,0 +1,91 @@
+//! Just enough of the OCI image layout to store a registry as an artifact:
+//! the index as one gzipped tarball layer and every crate file as a layer of
+//! its own, named after the file so tools like `oras` can pull them as is.
+
+use anyhow::Context as _;
+use cargo::util::errors::*;
+use cargo_util::Sha256;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+
+pub const ARTIFACT_TYPE: &str = "application/vnd.cargo-local-registry.v1";
+pub const MANIFEST_TYPE: &str = "application/vnd.oci.image.manifest.v1+json";
+pub const INDEX_TYPE: &str = "application/vnd.oci.image.index.v1+json";
+pub const EMPTY_TYPE: &str = "application/vnd.oci.empty.v1+json";
+pub const LAYER_TYPE: &str = "application/vnd.oci.image.layer.v1.tar+gzip";
+pub const TITLE: &str = "org.opencontainers.image.title";
+pub const REF_NAME: &str = "org.opencontainers.image.ref.name";
+/// Tells `oras pull` to unpack a layer rather than write it as a file.
+pub const UNPACK: &str = "io.deis.oras.content.unpack";
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Descriptor {
+    pub media_type: String,
+    pub digest: String,
+    pub size: u64,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub annotations: BTreeMap<String, String>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Manifest {
+    pub schema_version: u32,
+    pub media_type: String,
+    pub artifact_type: String,
+    pub config: Descriptor,
+    pub layers: Vec<Descriptor>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Index {
+    pub schema_version: u32,
+    #[serde(default)]
+    pub media_type: Option<String>,
+    pub manifests: Vec<Descriptor>,
+}
+
+/// Stores `contents` under `blobs/sha256` in the layout at `root`.
+pub fn write_blob(root: &Path, media_type: &str, contents: &[u8]) -> CargoResult<Descriptor> {
+    let mut sha = Sha256::new();
+    let digest = format!("sha256:{}", sha.update(contents).finish_hex());
+    let path = blob_path(root, &digest)?;
+    fs::create_dir_all(path.parent().unwrap())?;
+    File::create(&path).and_then(|mut f| f.write_all(contents))?;
+    Ok(Descriptor {
+        media_type: media_type.to_string(),
+        digest,
+        size: contents.len() as u64,
+        annotations: BTreeMap::new(),
+    })
+}
+
+/// Reads the blob `desc` points at from the layout at `root`, checking its
+/// size and digest.
+pub fn read_blob(root: &Path, desc: &Descriptor) -> CargoResult<Vec<u8>> {
+    let path = blob_path(root, &desc.digest)?;
+    let mut contents = Vec::new();
+    File::open(&path)
+        .and_then(|mut f| f.read_to_end(&mut contents))
+        .with_context(|| format!("failed to read blob `{}`", desc.digest))?;
+    let mut sha = Sha256::new();
+    let actual = format!("sha256:{}", sha.update(&contents).finish_hex());
+    if actual != desc.digest || contents.len() as u64 != desc.size {
+        anyhow::bail!("blob `{}` is corrupt, found {}", desc.digest, actual);
+    }
+    Ok(contents)
+}
+
+fn blob_path(root: &Path, digest: &str) -> CargoResult<PathBuf> {
+    match digest.split_once(':') {
+        Some(("sha256", hex)) if hex.len() == 64 && hex.chars().all(|c| c.is_ascii_hexdigit()) => {
+            Ok(root.join("blobs/sha256").join(hex))
+        }
+        _ => anyhow::bail!("unsupported digest `{}`", digest),
+    }
+}
@@ -0,0 +1,158 @@
+//! Policy files restricting what may enter a registry, given with `--policy`:
+//!
+//! ```toml
+//! deny = ["openssl-sys", "time@<0.2"]
+//! allow-only = []
+//!
+//! [licenses]
+//! allow = ["MIT", "Apache-2.0"]
+//! deny = ["GPL-3.0"]
+//! ```
+
+use crate::read;
+use anyhow::Context as _;
+use cargo::util::errors::*;
+use serde::Deserialize;
+use std::path::Path;
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Policy {
+    /// Crates never allowed, as `NAME[@VERSION_REQ]`.
+    #[serde(default)]
+    deny: Vec<String>,
+    /// When not empty, the only crates allowed, as `NAME[@VERSION_REQ]`.
+    #[serde(default)]
+    allow_only: Vec<String>,
+    #[serde(default)]
+    licenses: LicensePolicy,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct LicensePolicy {
+    /// When not empty, the only licenses allowed.
+    #[serde(default)]
+    allow: Vec<String>,
+    #[serde(default)]
+    deny: Vec<String>,
+}
+
+impl Policy {
+    pub fn load(path: &Path) -> CargoResult<Policy> {
+        let policy: Policy = toml::from_str(&read(path)?)
+            .with_context(|| format!("invalid policy `{}`", path.display()))?;
+        for spec in policy.deny.iter().chain(policy.allow_only.iter()) {
+            parse_spec(spec).with_context(|| format!("invalid policy `{}`", path.display()))?;
+        }
+        Ok(policy)
+    }
+
+    /// Checks a crate version against the `deny` and `allow-only` lists,
+    /// returning why it isn't allowed otherwise.
+    pub fn check_crate(&self, name: &str, version: &semver::Version) -> Result<(), String> {
+        let matches = |spec: &String| {
+            let (spec_name, req) = parse_spec(spec).unwrap();
+            spec_name == name && req.matches(version)
+        };
+        if let Some(spec) = self.deny.iter().find(|s| matches(s)) {
+            return Err(format!("denied by `{}`", spec));
+        }
+        if !self.allow_only.is_empty() && !self.allow_only.iter().any(matches) {
+            return Err("not in `allow-only`".to_string());
+        }
+        Ok(())
+    }
+
+    /// Checks a crate's SPDX license expression, where `MIT OR Apache-2.0` is
+    /// fine as long as one of the two is, returning why it isn't otherwise.
+    pub fn check_license(&self, license: Option<&str>) -> Result<(), String> {
+        let license = match license {
+            Some(license) => license,
+            None if self.licenses.allow.is_empty() => return Ok(()),
+            None => return Err("no SPDX license".to_string()),
+        };
+        let tokens = tokenize(license);
+        let mut pos = 0;
+        let allowed = self.eval_or(&tokens, &mut pos);
+        match allowed {
+            Some(true) if pos == tokens.len() => Ok(()),
+            Some(_) if pos == tokens.len() => Err(format!("license `{}` isn't allowed", license)),
+            _ => Err(format!("invalid license expression `{}`", license)),
+        }
+    }
+
+    fn allows(&self, id: &str) -> bool {
+        !self.licenses.deny.iter().any(|d| d == id)
+            && (self.licenses.allow.is_empty() || self.licenses.allow.iter().any(|a| a == id))
+    }
+
+    fn eval_or(&self, tokens: &[String], pos: &mut usize) -> Option<bool> {
+        let mut allowed = self.eval_and(tokens, pos)?;
+        while tokens.get(*pos).map(|t| t.as_str()) == Some("OR") {
+            *pos += 1;
+            allowed |= self.eval_and(tokens, pos)?;
+        }
+        Some(allowed)
+    }
+
+    fn eval_and(&self, tokens: &[String], pos: &mut usize) -> Option<bool> {
+        let mut allowed = self.eval_term(tokens, pos)?;
+        while tokens.get(*pos).map(|t| t.as_str()) == Some("AND") {
+            *pos += 1;
+            allowed &= self.eval_term(tokens, pos)?;
+        }
+        Some(allowed)
+    }
+
+    fn eval_term(&self, tokens: &[String], pos: &mut usize) -> Option<bool> {
+        let token = tokens.get(*pos)?;
+        *pos += 1;
+        match token.as_str() {
+            "(" => {
+                let allowed = self.eval_or(tokens, pos)?;
+                if tokens.get(*pos)? != ")" {
+                    return None;
+                }
+                *pos += 1;
+                Some(allowed)
+            }
+            ")" | "AND" | "OR" | "WITH" => None,
+            _ => {
+                // An exception is only ever allowed along with its license,
+                // so `Apache-2.0 WITH LLVM-exception` is checked as a whole.
+                if tokens.get(*pos).map(|t| t.as_str()) == Some("WITH") {
+                    let id = format!("{} WITH {}", token, tokens.get(*pos + 1)?);
+                    *pos += 2;
+                    Some(self.allows(&id))
+                } else {
+                    Some(self.allows(token))
+                }
+            }
+        }
+    }
+}
+
+/// Splits a `NAME[@VERSION_REQ]` spec of the crate lists.
+fn parse_spec(spec: &str) -> CargoResult<(&str, semver::VersionReq)> {
+    let (name, req) = spec.split_once('@').unwrap_or((spec, "*"));
+    if name.is_empty() {
+        anyhow::bail!("invalid crate `{}`", spec);
+    }
+    let req = req
+        .parse()
+        .with_context(|| format!("invalid version requirement in `{}`", spec))?;
+    Ok((name, req))
+}
+
+/// Splits a license expression, accepting the `MIT/Apache-2.0` style Cargo
+/// still does.
+fn tokenize(license: &str) -> Vec<String> {
+    license
+        .replace('/', " OR ")
+        .replace('(', " ( ")
+        .replace(')', " ) ")
+        .split_whitespace()
+        .map(|t| t.to_string())
+        .collect()
+}
@@ -0,0 +1,105 @@
+use indicatif::{HumanBytes, ProgressBar, ProgressStyle};
+use std::path::Path;
+use std::sync::Mutex;
+
+/// Receives the progress of a sync or add, e.g. to drive a progress bar.
+pub trait Progress: Send + Sync {
+    fn report(&self, event: &Event<'_>);
+}
+
+/// Something that happened during a sync or add. Events and their fields may
+/// be added over time.
+#[non_exhaustive]
+pub enum Event<'a> {
+    /// Cargo is downloading the `crates` crates to add, other than those it
+    /// already has.
+    #[non_exhaustive]
+    Downloading { crates: usize },
+    /// A crate is about to be copied, or packaged, into the registry.
+    #[non_exhaustive]
+    Writing { name: &'a str, version: &'a str },
+    /// A crate and its index entry are in the registry. `bytes` is the size
+    /// of its crate file, or 0 when crate files are hosted elsewhere, and
+    /// `packaged` whether it was packaged from a git checkout rather than
+    /// copied.
+    #[non_exhaustive]
+    Written {
+        name: &'a str,
+        version: &'a str,
+        bytes: u64,
+        packaged: bool,
+    },
+    /// The index file at `path` was written.
+    #[non_exhaustive]
+    IndexUpdated { path: &'a Path },
+    /// The crate file at `path`, no longer wanted, was removed.
+    #[non_exhaustive]
+    Deleted { path: &'a Path },
+}
+
+/// Draws the progress of a sync or add as a bar on stderr, when it is a
+/// terminal. Cargo draws its own while downloading, so the bar only shows up
+/// once crates are being written.
+#[derive(Default)]
+pub(crate) struct Bar {
+    state: Mutex<BarState>,
+}
+
+#[derive(Default)]
+struct BarState {
+    crates: u64,
+    bytes: u64,
+    bar: Option<ProgressBar>,
+}
+
+impl Progress for Bar {
+    fn report(&self, event: &Event<'_>) {
+        let mut state = self.state.lock().unwrap();
+        match *event {
+            Event::Downloading { crates } => {
+                if let Some(bar) = state.bar.take() {
+                    bar.finish_and_clear();
+                }
+                state.crates = crates as u64;
+            }
+            Event::Writing { name, version } => {
+                let crates = state.crates;
+                let bytes = state.bytes;
+                let bar = state.bar.get_or_insert_with(|| {
+                    let style = ProgressStyle::with_template(
+                        "{prefix:>12.cyan.bold} [{bar:27}] {pos}/{len} ETA {eta} {wide_msg}",
+                    )
+                    .unwrap()
+                    .progress_chars("=> ");
+                    ProgressBar::new(crates)
+                        .with_style(style)
+                        .with_prefix("Writing")
+                });
+                bar.set_message(format!(
+                    "{} {} ({} written)",
+                    name,
+                    version,
+                    HumanBytes(bytes)
+                ));
+            }
+            Event::Written { bytes, .. } => {
+                state.bytes += bytes;
+                if let Some(ref bar) = state.bar {
+                    bar.inc(1);
+                    if bar.length() == Some(bar.position()) {
+                        bar.finish_and_clear();
+                    }
+                }
+            }
+            Event::IndexUpdated { .. } | Event::Deleted { .. } => {}
+        }
+    }
+}
+
+impl Drop for Bar {
+    fn drop(&mut self) {
+        if let Some(bar) = self.state.get_mut().unwrap().bar.take() {
+            bar.finish_and_clear();
+        }
+    }
+}
@@ -0,0 +1,160 @@
+//! Where each crate of a registry came from, when, and who added it, kept in
+//! `provenance.json` at the root of the registry.
+
+use crate::message::{self, MessageFormat};
+use crate::{add, index, read};
+use anyhow::Context as _;
+use cargo::util::errors::*;
+use cargo::util::GlobalContext;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::Path;
+use std::time::SystemTime;
+
+pub const FILE: &str = "provenance.json";
+
+#[derive(clap::Args)]
+pub struct InfoOptions {
+    /// Path to the local registry, defaults to the configured one
+    #[arg(default_value_t, hide_default_value = true)]
+    pub path: String,
+    /// Crate to show, as `NAME` or `NAME@VERSION_REQ`
+    #[arg(default_value_t, hide_default_value = true)]
+    pub spec: String,
+    /// Print the provenance as a JSON array, deprecated in favor of
+    /// `--message-format json`
+    #[arg(long, default_value_t = false, hide = true)]
+    pub json: bool,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone)]
+pub struct Provenance {
+    pub name: String,
+    pub version: String,
+    pub cksum: String,
+    /// Registry URL the crate file was downloaded from, git repository and
+    /// revision it was packaged from, or directory it was imported from.
+    pub source: String,
+    /// The command that added the crate, e.g. `sync` or `add`.
+    pub command: String,
+    pub added_at: String,
+    pub added_by: Option<String>,
+    pub tool: String,
+}
+
+impl Provenance {
+    pub fn new(name: &str, version: &str, cksum: &str, source: String, command: &str) -> Self {
+        Provenance {
+            name: name.to_string(),
+            version: version.to_string(),
+            cksum: cksum.to_string(),
+            source,
+            command: command.to_string(),
+            added_at: humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+            added_by: env::var("USER").or_else(|_| env::var("USERNAME")).ok(),
+            tool: format!("cargo-local-registry {}", env!("CARGO_PKG_VERSION")),
+        }
+    }
+
+    fn key(&self) -> String {
+        format!("{}-{}", self.name, self.version)
+    }
+}
+
+/// Merges `added` into the provenance of the registry at `root`, and drops
+/// that of crates no longer in its index. Crates added again from the same
+/// source with the same checksum keep their original record.
+pub fn record(root: &Path, added: Vec<Provenance>) -> CargoResult<()> {
+    let mut records = load(root)?;
+    for provenance in added {
+        match records.get(&provenance.key()) {
+            Some(prev) if prev.cksum == provenance.cksum && prev.source == provenance.source => {}
+            _ => {
+                records.insert(provenance.key(), provenance);
+            }
+        }
+    }
+    let present = index::entries(root)?
+        .into_iter()
+        .map(|pkg| format!("{}-{}", pkg.name, pkg.vers))
+        .collect::<BTreeSet<_>>();
+    records.retain(|key, _| present.contains(key));
+
+    let json = serde_json::to_string_pretty(&records).unwrap();
+    File::create(root.join(FILE)).and_then(|mut f| f.write_all(json.as_bytes()))?;
+    Ok(())
+}
+
+fn load(root: &Path) -> CargoResult<BTreeMap<String, Provenance>> {
+    let path = root.join(FILE);
+    if !path.exists() {
+        return Ok(BTreeMap::new());
+    }
+    serde_json::from_str(&read(&path)?).with_context(|| format!("invalid `{}`", path.display()))
+}
+
+/// Prints the provenance of every version of a crate in the registry
+/// matching the requirement.
+pub fn info(
+    options: &InfoOptions,
+    format: MessageFormat,
+    config: &GlobalContext,
+) -> CargoResult<()> {
+    let root = Path::new(&options.path);
+    let (name, req) = add::parse_spec(&options.spec)?;
+    let records = load(root)?;
+    let mut versions = index::entries(root)?
+        .into_iter()
+        .filter(|pkg| pkg.name == name)
+        .filter_map(|pkg| semver::Version::parse(&pkg.vers).ok().map(|v| (v, pkg)))
+        .filter(|(v, _)| req.matches(v))
+        .collect::<Vec<_>>();
+    versions.sort_by(|a, b| a.0.cmp(&b.0));
+    if versions.is_empty() {
+        anyhow::bail!("no version of `{}` in `{}`", options.spec, root.display());
+    }
+
+    if options.json {
+        config
+            .shell()
+            .warn("`--json` is deprecated, use `--message-format json` instead")?;
+        let found = versions
+            .iter()
+            .map(|(_, pkg)| records.get(&format!("{}-{}", pkg.name, pkg.vers)))
+            .collect::<Vec<_>>();
+        println!("{}", serde_json::to_string(&found).unwrap());
+        return Ok(());
+    }
+    if format == MessageFormat::Json {
+        for (_, pkg) in versions.iter() {
+            message::emit(json!({
+                "reason": "crate-info",
+                "crate": pkg.name,
+                "version": pkg.vers,
+                "checksum": pkg.cksum,
+                "provenance": records.get(&format!("{}-{}", pkg.name, pkg.vers)),
+            }));
+        }
+        return Ok(());
+    }
+    for (_, pkg) in versions.iter() {
+        println!("{} {}", pkg.name, pkg.vers);
+        println!("  checksum: {}", pkg.cksum);
+        match records.get(&format!("{}-{}", pkg.name, pkg.vers)) {
+            Some(p) => {
+                println!("  source: {}", p.source);
+                match p.added_by {
+                    Some(ref user) => println!("  added: {} by {}", p.added_at, user),
+                    None => println!("  added: {}", p.added_at),
+                }
+                println!("  via: {} ({})", p.tool, p.command);
+            }
+            None => println!("  no provenance recorded"),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,123 @@
+use crate::provenance::{self, Provenance};
+use crate::{crate_path, registry_pkg, replace_file, write_index_entry};
+use anyhow::Context as _;
+use cargo::core::dependency::DepKind;
+use cargo::core::resolver::CliFeatures;
+use cargo::core::{SourceId, Workspace};
+use cargo::ops::{self, PackageOpts, Packages};
+use cargo::util::errors::*;
+use cargo::util::important_paths::find_root_manifest_for_wd;
+use cargo::util::GlobalContext;
+use cargo_util::Sha256;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(clap::Args)]
+pub struct PublishLocalOptions {
+    /// Path to the local registry, defaults to the configured one
+    #[arg(default_value_t, hide_default_value = true)]
+    pub path: String,
+    /// Path to the Cargo.toml of the workspace to publish, defaults to the
+    /// one of the current directory
+    #[arg(long, value_name = "PATH")]
+    pub manifest_path: Option<String>,
+    /// Only publish these members of the workspace
+    #[arg(short, long = "package", value_name = "SPEC")]
+    pub packages: Vec<String>,
+    /// Allow publishing with uncommitted changes
+    #[arg(long, default_value_t = false)]
+    pub allow_dirty: bool,
+}
+
+/// Packages every publishable member of a workspace with `cargo package` and
+/// adds the resulting crate files to the registry, replacing any previous
+/// entry for the same version.
+///
+/// Dependencies between members become plain version requirements, as they
+/// would on crates.io, so they resolve against the registry itself. Other
+/// dependencies aren't added, use `--sync` or `add` for those.
+pub fn publish_local(
+    options: &PublishLocalOptions,
+    registry_id: &SourceId,
+    config: &GlobalContext,
+) -> CargoResult<BTreeMap<SourceId, PathBuf>> {
+    let manifest = match options.manifest_path {
+        Some(ref path) => config.cwd().join(path),
+        None => find_root_manifest_for_wd(config.cwd())?,
+    };
+    let ws = Workspace::new(&manifest, config)?;
+
+    let members = ws
+        .members()
+        .filter(|pkg| pkg.publish().as_ref().is_none_or(|r| !r.is_empty()))
+        .filter(|pkg| {
+            options.packages.is_empty() || options.packages.iter().any(|p| **p == *pkg.name())
+        })
+        .collect::<Vec<_>>();
+    if members.is_empty() {
+        anyhow::bail!("no publishable packages in `{}`", ws.root().display());
+    }
+
+    let opts = PackageOpts {
+        gctx: config,
+        list: false,
+        check_metadata: false,
+        allow_dirty: options.allow_dirty,
+        verify: false,
+        jobs: None,
+        keep_going: false,
+        to_package: Packages::Packages(members.iter().map(|p| p.name().to_string()).collect()),
+        targets: Vec::new(),
+        cli_features: CliFeatures::new_all(false),
+    };
+    ops::package(&ws, &opts)?;
+
+    let local_dst = Path::new(&options.path);
+    let mut added = Vec::new();
+    for pkg in members {
+        let id = pkg.package_id();
+        let src = ws
+            .target_dir()
+            .join("package")
+            .join(id.tarball_name())
+            .into_path_unlocked();
+        let dst = crate_path(local_dst, &id.name(), &id.version().to_string());
+        fs::create_dir_all(dst.parent().unwrap())?;
+        replace_file(&dst)?;
+        fs::copy(&src, &dst).with_context(|| {
+            format!("failed to copy `{}` to `{}`", src.display(), dst.display())
+        })?;
+
+        let mut sha = Sha256::new();
+        let mut entry = registry_pkg(pkg, sha.update_path(&dst)?.finish_hex());
+        // Dev-dependencies without a version are dropped when packaging, so
+        // they can't be in the index either.
+        let unversioned = pkg
+            .dependencies()
+            .iter()
+            .filter(|dep| dep.kind() == DepKind::Development && !dep.specified_req())
+            .map(|dep| dep.name_in_toml().to_string())
+            .collect::<Vec<_>>();
+        entry.deps.retain(|dep| !unversioned.contains(&dep.name));
+        write_index_entry(local_dst, &entry)?;
+        added.push(Provenance::new(
+            &entry.name,
+            &entry.vers,
+            &entry.cksum,
+            id.source_id().as_url().to_string(),
+            "publish-local",
+        ));
+        config
+            .shell()
+            .status("Published", format!("{} to `{}`", id, local_dst.display()))?;
+    }
+    provenance::record(local_dst, added)?;
+
+    let mut sources = BTreeMap::new();
+    sources.insert(
+        *registry_id,
+        local_dst.canonicalize().unwrap_or(local_dst.to_path_buf()),
+    );
+    Ok(sources)
+}
@@ -0,0 +1,207 @@
+use crate::add::{self, AddOptions};
+use crate::message::MessageFormat;
+use crate::{create_index, finish, sync, Command, Error, IndexFormat, Layout, Options, Progress};
+use cargo::core::SourceId;
+use cargo::util::errors::*;
+use cargo::util::GlobalContext;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use url::Url;
+
+/// A local registry on disk, to do what `--sync` and `add` do without going
+/// through the command line.
+///
+/// ```no_run
+/// use cargo_local_registry::{global_context, Registry, SyncOptions};
+/// use std::path::Path;
+///
+/// let config = global_context()?;
+/// let registry = Registry::new("path/to/registry");
+/// registry.sync(Path::new("Cargo.lock"), &SyncOptions::default(), &config)?;
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+pub struct Registry {
+    path: PathBuf,
+}
+
+/// How to sync or add to a registry, each field being the command line
+/// option of the same name. Options may be added over time, so start from
+/// `SyncOptions::default()` and set what's needed with the method of the same
+/// name:
+///
+/// ```
+/// use cargo_local_registry::SyncOptions;
+///
+/// let options = SyncOptions::default().git(true).keep_versions(["3"]);
+/// ```
+#[derive(Default)]
+#[non_exhaustive]
+pub struct SyncOptions {
+    pub manifest_path: Option<String>,
+    pub host: Option<String>,
+    pub git: bool,
+    pub no_delete: bool,
+    pub keep_versions: Vec<String>,
+    pub dl: Option<String>,
+    pub dl_manifest: Option<String>,
+    pub split_sources: bool,
+    pub shard_crates: bool,
+    pub store: Option<String>,
+    pub policy: Option<String>,
+    pub max_crate_size: Option<u64>,
+    pub sha256_files: bool,
+    pub sign_key: Option<String>,
+    pub index_format: IndexFormat,
+    pub layout: Layout,
+    /// Where to report the progress of the sync to.
+    pub progress: Option<Arc<dyn Progress>>,
+}
+
+/// Defines a method setting each of the given fields of `SyncOptions`, the
+/// optional ones taking what they are set to.
+macro_rules! setters {
+    ($($field:ident: $ty:ty,)* ; $($optional:ident: $inner:ty,)*) => {
+        $(
+            pub fn $field(mut self, $field: $ty) -> SyncOptions {
+                self.$field = $field;
+                self
+            }
+        )*
+        $(
+            pub fn $optional(mut self, $optional: impl Into<$inner>) -> SyncOptions {
+                self.$optional = Some($optional.into());
+                self
+            }
+        )*
+    };
+}
+
+impl SyncOptions {
+    setters! {
+        git: bool,
+        no_delete: bool,
+        split_sources: bool,
+        shard_crates: bool,
+        sha256_files: bool,
+        index_format: IndexFormat,
+        layout: Layout,
+        progress: Option<Arc<dyn Progress>>,
+        ;
+        manifest_path: String,
+        host: String,
+        dl: String,
+        dl_manifest: String,
+        store: String,
+        policy: String,
+        max_crate_size: u64,
+        sign_key: String,
+    }
+
+    pub fn keep_versions<S: Into<String>>(
+        mut self,
+        keep_versions: impl IntoIterator<Item = S>,
+    ) -> SyncOptions {
+        self.keep_versions = keep_versions.into_iter().map(Into::into).collect();
+        self
+    }
+}
+
+impl Registry {
+    pub fn new(path: impl Into<PathBuf>) -> Registry {
+        Registry { path: path.into() }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Syncs the registry with `lockfile`, returning the local registry each
+    /// source ended up in, as `--sync` does.
+    pub fn sync(
+        &self,
+        lockfile: &Path,
+        options: &SyncOptions,
+        config: &GlobalContext,
+    ) -> Result<BTreeMap<SourceId, PathBuf>, Error> {
+        let options = self.options(options, None);
+        let id = self.prepare(&options, config)?;
+        let sources = sync(lockfile, &self.path, &id, &options, config)?;
+        finish(&sources, &options)?;
+        Ok(sources)
+    }
+
+    /// Adds `crates`, each as `NAME` or `NAME@VERSION_REQ`, along with
+    /// everything they depend on, as `add` does.
+    pub fn add(
+        &self,
+        crates: &[&str],
+        options: &SyncOptions,
+        config: &GlobalContext,
+    ) -> Result<BTreeMap<SourceId, PathBuf>, Error> {
+        let add = AddOptions {
+            path: self.path.display().to_string(),
+            crates: crates.iter().map(|c| c.to_string()).collect(),
+            from_file: None,
+            features: Vec::new(),
+            no_default_features: false,
+            dev: false,
+            git: None,
+            rev: None,
+            dry_run: false,
+            allow_yanked: false,
+            latest: true,
+            all_matching: false,
+        };
+        let options = self.options(options, Some(Command::Add(add)));
+        let id = self.prepare(&options, config)?;
+        let add = match options.command {
+            Some(Command::Add(ref add)) => add,
+            _ => unreachable!(),
+        };
+        let sources = add::add(add, &id, &options, config)?;
+        finish(&sources, &options)?;
+        Ok(sources)
+    }
+
+    /// Creates the index directory and returns the registry to sync with.
+    fn prepare(&self, options: &Options, config: &GlobalContext) -> CargoResult<SourceId> {
+        create_index(&self.path, options)?;
+        match options.host {
+            Some(ref s) => SourceId::for_registry(&Url::parse(s)?),
+            None => SourceId::crates_io_maybe_sparse_http(config),
+        }
+    }
+
+    fn options(&self, options: &SyncOptions, command: Option<Command>) -> Options {
+        Options {
+            command,
+            sync: None,
+            manifest_path: options.manifest_path.clone(),
+            host: options.host.clone(),
+            git: options.git,
+            verbose: 0,
+            quiet: false,
+            color: None,
+            locked: false,
+            offline: false,
+            frozen: false,
+            message_format: MessageFormat::Human,
+            no_delete: options.no_delete,
+            sign_key: options.sign_key.clone(),
+            policy: options.policy.clone(),
+            keep_versions: options.keep_versions.clone(),
+            dl: options.dl.clone(),
+            dl_manifest: options.dl_manifest.clone(),
+            split_sources: options.split_sources,
+            shard_crates: options.shard_crates,
+            store: options.store.clone(),
+            max_crate_size: options.max_crate_size,
+            sha256_files: options.sha256_files,
+            index_format: Some(options.index_format),
+            layout: Some(options.layout),
+            path: Some(self.path.display().to_string()),
+            progress: options.progress.clone(),
+        }
+    }
+}
@@ -0,0 +1,185 @@
+use crate::{crate_license, index};
+use cargo::util::errors::*;
+use cargo::util::GlobalContext;
+use cargo_util::Sha256;
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::Path;
+use std::time::SystemTime;
+
+#[derive(clap::Args)]
+pub struct SbomOptions {
+    /// Path to the local registry, defaults to the configured one
+    #[arg(default_value_t, hide_default_value = true)]
+    pub path: String,
+    /// Format of the SBOM
+    #[arg(long, value_enum)]
+    pub format: SbomFormat,
+    /// Write the SBOM to FILE instead of stdout
+    #[arg(short, long, value_name = "FILE")]
+    pub output: Option<String>,
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+pub enum SbomFormat {
+    /// CycloneDX 1.5, as JSON
+    Cyclonedx,
+    /// SPDX 2.3, as JSON
+    Spdx,
+}
+
+/// What the SBOM says about each crate version of the registry.
+struct Component {
+    name: String,
+    version: String,
+    cksum: String,
+    license: Option<String>,
+    license_file: Option<String>,
+}
+
+impl Component {
+    fn purl(&self) -> String {
+        format!("pkg:cargo/{}@{}", self.name, self.version)
+    }
+}
+
+/// Lists every crate version of the registry with its checksum and license,
+/// as declared in the manifest packaged in its crate file.
+pub fn sbom(options: &SbomOptions, config: &GlobalContext) -> CargoResult<()> {
+    let root = Path::new(&options.path);
+    let mut entries = index::entries(root)?;
+    entries.sort_by(|a, b| (&a.name, &a.vers).cmp(&(&b.name, &b.vers)));
+    let mut components = Vec::new();
+    for pkg in entries {
+        let license = crate_license(root, &pkg.name, &pkg.vers, config)?.unwrap_or_default();
+        components.push(Component {
+            name: pkg.name,
+            version: pkg.vers,
+            cksum: pkg.cksum,
+            license: license.license,
+            license_file: license.file,
+        });
+    }
+
+    let created = humantime::format_rfc3339_seconds(SystemTime::now()).to_string();
+    let tool = format!("cargo-local-registry-{}", env!("CARGO_PKG_VERSION"));
+    let name = root
+        .canonicalize()?
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let doc = match options.format {
+        SbomFormat::Cyclonedx => cyclonedx(&components, &created),
+        SbomFormat::Spdx => spdx(&components, &name, &created, &tool),
+    };
+    let json = serde_json::to_string_pretty(&doc).unwrap();
+    match options.output {
+        Some(ref file) => File::create(file).and_then(|mut f| f.write_all(json.as_bytes()))?,
+        None => println!("{}", json),
+    }
+    Ok(())
+}
+
+fn cyclonedx(components: &[Component], created: &str) -> Value {
+    let components = components
+        .iter()
+        .map(|c| {
+            let licenses = match (&c.license, &c.license_file) {
+                (Some(license), _) => json!([{ "expression": spdx_expression(license) }]),
+                (None, Some(file)) => json!([{ "license": { "name": format!("see {}", file) } }]),
+                (None, None) => json!([]),
+            };
+            json!({
+                "type": "library",
+                "bom-ref": c.purl(),
+                "name": c.name,
+                "version": c.version,
+                "purl": c.purl(),
+                "hashes": [{ "alg": "SHA-256", "content": c.cksum }],
+                "licenses": licenses,
+            })
+        })
+        .collect::<Vec<_>>();
+    json!({
+        "bomFormat": "CycloneDX",
+        "specVersion": "1.5",
+        "version": 1,
+        "metadata": {
+            "timestamp": created,
+            "tools": [{ "name": "cargo-local-registry", "version": env!("CARGO_PKG_VERSION") }],
+        },
+        "components": components,
+    })
+}
+
+fn spdx(components: &[Component], name: &str, created: &str, tool: &str) -> Value {
+    // The namespace only has to be unique to this document, which the
+    // checksums of what it lists make it.
+    let mut sha = Sha256::new();
+    for c in components {
+        sha.update(c.cksum.as_bytes());
+    }
+    let namespace = format!(
+        "https://spdx.org/spdxdocs/{}-{}",
+        name,
+        &sha.finish_hex()[..16]
+    );
+    // Characters SPDX IDs can't have are replaced, so crates like `foo_bar`
+    // and `foo-bar` would get the same ID, which a numeric suffix tells apart.
+    let mut ids = HashSet::new();
+    let packages = components
+        .iter()
+        .map(|c| {
+            let base = format!("SPDXRef-Package-{}-{}", c.name, c.version).replace(
+                |ch: char| !ch.is_ascii_alphanumeric() && ch != '-' && ch != '.',
+                "-",
+            );
+            let mut id = base.clone();
+            let mut n = 1;
+            while !ids.insert(id.clone()) {
+                n += 1;
+                id = format!("{}-{}", base, n);
+            }
+            let license = match c.license {
+                Some(ref license) => spdx_expression(license),
+                None => "NOASSERTION".to_string(),
+            };
+            json!({
+                "name": c.name,
+                "SPDXID": id,
+                "versionInfo": c.version,
+                "downloadLocation": "NOASSERTION",
+                "filesAnalyzed": false,
+                "checksums": [{ "algorithm": "SHA256", "checksumValue": c.cksum }],
+                "licenseConcluded": "NOASSERTION",
+                "licenseDeclared": license,
+                "externalRefs": [{
+                    "referenceCategory": "PACKAGE-MANAGER",
+                    "referenceType": "purl",
+                    "referenceLocator": c.purl(),
+                }],
+            })
+        })
+        .collect::<Vec<_>>();
+    json!({
+        "spdxVersion": "SPDX-2.3",
+        "dataLicense": "CC0-1.0",
+        "SPDXID": "SPDXRef-DOCUMENT",
+        "name": name,
+        "documentNamespace": namespace,
+        "creationInfo": { "created": created, "creators": [format!("Tool: {}", tool)] },
+        "packages": packages,
+    })
+}
+
+/// Turns the old `MIT/Apache-2.0` style Cargo still accepts into a proper SPDX
+/// expression.
+fn spdx_expression(license: &str) -> String {
+    license
+        .split('/')
+        .map(|l| l.trim())
+        .collect::<Vec<_>>()
+        .join(" OR ")
+}
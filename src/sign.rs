@@ -0,0 +1,287 @@
+//! Signed manifests of a registry: `SHA256SUMS` lists every file with its
+//! sha256, and `SHA256SUMS.minisig` is an ed25519 signature of it in the
+//! format of minisign.
+
+use crate::bundle::parse_manifest;
+use crate::message::{self, MessageFormat};
+use crate::{check_layout, read, Error};
+use anyhow::Context as _;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine as _;
+use cargo::util::errors::*;
+use cargo::util::GlobalContext;
+use cargo_util::Sha256;
+use ed25519_compact::{KeyPair, PublicKey, SecretKey, Signature};
+use serde_json::json;
+use std::fs::{File, OpenOptions};
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const SUMS: &str = "SHA256SUMS";
+pub const SIGNATURE: &str = "SHA256SUMS.minisig";
+/// Algorithm of minisign keys and of signatures of the data itself, rather
+/// than of its hash.
+const ALG: &[u8] = b"Ed";
+
+#[derive(clap::Args)]
+pub struct KeygenOptions {
+    /// Path to write the secret key to, the public key goes next to it with
+    /// a `.pub` extension
+    pub file: String,
+}
+
+#[derive(clap::Args)]
+pub struct VerifyOptions {
+    /// Path to the local registry, defaults to the configured one
+    #[arg(default_value_t, hide_default_value = true)]
+    pub path: String,
+    /// Check `SHA256SUMS` is signed by this public key before checking the
+    /// files against it
+    #[arg(long, value_name = "PUBLIC_KEY")]
+    pub signature: Option<String>,
+}
+
+pub fn keygen(options: &KeygenOptions, config: &GlobalContext) -> CargoResult<()> {
+    let secret = Path::new(&options.file);
+    let public = PathBuf::from(format!("{}.pub", options.file));
+    if secret.exists() || public.exists() {
+        anyhow::bail!("`{}` already exists", options.file);
+    }
+    let keypair = KeyPair::generate();
+    let id = key_id(&keypair.pk);
+    write_key(
+        secret,
+        "cargo-local-registry secret key",
+        &[ALG, &id, keypair.sk.as_ref()].concat(),
+        false,
+    )?;
+    write_key(
+        &public,
+        &format!("minisign public key {}", hex_id(&id)),
+        &[ALG, &id, keypair.pk.as_ref()].concat(),
+        true,
+    )?;
+    config.shell().status(
+        "Generated",
+        format!("key {} in `{}`", hex_id(&id), public.display()),
+    )?;
+    Ok(())
+}
+
+/// Writes `SHA256SUMS` for every file under `dir` and signs it with the
+/// secret key at `key`.
+pub fn sign(dir: &Path, key: &Path) -> CargoResult<()> {
+    let (id, sk) = read_key(key)?;
+    let sk = SecretKey::from_slice(&sk)
+        .map_err(|_| anyhow::format_err!("invalid secret key `{}`", key.display()))?;
+
+    let mut sums = String::new();
+    for file in signed_files(dir)? {
+        let mut sha = Sha256::new();
+        sums.push_str(&format!(
+            "{}  {}\n",
+            sha.update_path(&file)?.finish_hex(),
+            file_name(dir, &file)?
+        ));
+    }
+    File::create(dir.join(SUMS)).and_then(|mut f| f.write_all(sums.as_bytes()))?;
+
+    let signature = sk.sign(sums.as_bytes(), None);
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let trusted = format!("timestamp:{}\tfile:{}", timestamp, SUMS);
+    let global = sk.sign([signature.as_ref(), trusted.as_bytes()].concat(), None);
+    let contents = format!(
+        "untrusted comment: signature from cargo-local-registry secret key\n{}\n\
+         trusted comment: {}\n{}\n",
+        BASE64.encode([ALG, &id, signature.as_ref()].concat()),
+        trusted,
+        BASE64.encode(global.as_ref())
+    );
+    File::create(dir.join(SIGNATURE)).and_then(|mut f| f.write_all(contents.as_bytes()))?;
+    Ok(())
+}
+
+/// Checks the layout of the registry, then every file against `SHA256SUMS`
+/// if it has one, and the signature of that when given a public key.
+pub fn verify(
+    options: &VerifyOptions,
+    format: MessageFormat,
+    config: &GlobalContext,
+) -> CargoResult<()> {
+    let dir = Path::new(&options.path);
+    let mut failed = check_layout(dir)?;
+
+    let signed = dir.join(SUMS).exists() || options.signature.is_some();
+    if signed {
+        let sums = read(&dir.join(SUMS))?;
+        if let Some(ref key) = options.signature {
+            verify_signature(sums.as_bytes(), &dir.join(SIGNATURE), Path::new(key))?;
+        }
+        let mut expected = parse_manifest(&sums)?;
+        for file in signed_files(dir)?.iter() {
+            let name = file_name(dir, file)?;
+            let mut sha = Sha256::new();
+            match expected.remove(&name) {
+                Some(cksum) if cksum == sha.update_path(file)?.finish_hex() => {}
+                Some(_) => failed.push(format!("{} (checksum mismatch)", name)),
+                None => failed.push(format!("{} (not in {})", name, SUMS)),
+            }
+        }
+        let mut missing = expected.into_keys().collect::<Vec<_>>();
+        missing.sort();
+        failed.extend(
+            missing
+                .into_iter()
+                .map(|name| format!("{} (missing)", name)),
+        );
+    }
+    if !failed.is_empty() {
+        if format == MessageFormat::Json {
+            message::emit(json!({
+                "reason": "verify-failed",
+                "path": dir,
+                "problems": failed,
+            }));
+        }
+        return Err(Error::Integrity(format!(
+            "`{}` failed verification:\n  {}",
+            dir.display(),
+            failed.join("\n  ")
+        ))
+        .into());
+    }
+
+    let checked = match (signed, options.signature.is_some()) {
+        (_, true) => "layout, files and signature",
+        (true, false) => "layout and files",
+        (false, false) => "layout",
+    };
+    match format {
+        MessageFormat::Human => config
+            .shell()
+            .status("Verified", format!("{} of `{}`", checked, dir.display()))?,
+        MessageFormat::Json => message::emit(json!({
+            "reason": "verified",
+            "path": dir,
+            "checked": checked,
+        })),
+    }
+    Ok(())
+}
+
+fn verify_signature(sums: &[u8], path: &Path, key: &Path) -> CargoResult<()> {
+    let (key_id, pk) = read_key(key)?;
+    let pk = PublicKey::from_slice(&pk)
+        .map_err(|_| anyhow::format_err!("invalid public key `{}`", key.display()))?;
+
+    let contents = read(path)?;
+    let invalid = || anyhow::format_err!("invalid signature file `{}`", path.display());
+    let mut lines = contents.lines();
+    lines
+        .next()
+        .filter(|l| l.starts_with("untrusted comment:"))
+        .ok_or_else(invalid)?;
+    let signature = lines
+        .next()
+        .and_then(|l| BASE64.decode(l.trim()).ok())
+        .filter(|s| s.len() == 74 && &s[..2] == ALG)
+        .ok_or_else(invalid)?;
+    let trusted = lines
+        .next()
+        .and_then(|l| l.strip_prefix("trusted comment: "))
+        .ok_or_else(invalid)?;
+    let global = lines
+        .next()
+        .and_then(|l| BASE64.decode(l.trim()).ok())
+        .and_then(|s| Signature::from_slice(&s).ok())
+        .ok_or_else(invalid)?;
+
+    if signature[2..10] != key_id[..] {
+        return Err(Error::Integrity(format!(
+            "`{}` is signed with key {}, not {}",
+            path.display(),
+            hex_id(&signature[2..10]),
+            hex_id(&key_id)
+        ))
+        .into());
+    }
+    let sig = Signature::from_slice(&signature[10..]).map_err(|_| invalid())?;
+    pk.verify(sums, &sig)
+        .and_then(|()| pk.verify([&signature[10..], trusted.as_bytes()].concat(), &global))
+        .map_err(|_| Error::Integrity(format!("`{}` doesn't match its signature", SUMS)))?;
+    Ok(())
+}
+
+/// Lists the files a signature covers, which is everything under `dir` but
+/// hidden files, like a git index's `.git`, and the signature files.
+fn signed_files(dir: &Path) -> CargoResult<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(next) = dirs.pop() {
+        for entry in next.read_dir()? {
+            let path = entry?.path();
+            let name = path.file_name().unwrap().to_string_lossy();
+            if name.starts_with('.') || (next == dir && (name == SUMS || name == SIGNATURE)) {
+                continue;
+            } else if path.is_dir() {
+                dirs.push(path);
+            } else {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+fn file_name(dir: &Path, file: &Path) -> CargoResult<String> {
+    file.strip_prefix(dir)
+        .unwrap()
+        .to_str()
+        .map(|s| s.replace('\\', "/"))
+        .ok_or_else(|| anyhow::format_err!("non-utf8 path `{}`", file.display()))
+}
+
+/// Reads a key file in the format of minisign, an untrusted comment followed
+/// by the base64 of the algorithm, key id and key, returning the last two.
+fn read_key(path: &Path) -> CargoResult<([u8; 8], Vec<u8>)> {
+    let contents = read(path)?;
+    let key = contents
+        .lines()
+        .find(|l| !l.starts_with("untrusted comment:") && !l.trim().is_empty())
+        .and_then(|l| BASE64.decode(l.trim()).ok())
+        .filter(|k| k.len() > 10 && &k[..2] == ALG)
+        .ok_or_else(|| anyhow::format_err!("invalid key `{}`", path.display()))?;
+    Ok((key[2..10].try_into().unwrap(), key[10..].to_vec()))
+}
+
+/// Writes a key to `path`, which mustn't exist yet, readable only by its
+/// owner unless `public`.
+fn write_key(path: &Path, comment: &str, key: &[u8], public: bool) -> CargoResult<()> {
+    let contents = format!("untrusted comment: {}\n{}\n", comment, BASE64.encode(key));
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, if public { 0o644 } else { 0o600 });
+    #[cfg(not(unix))]
+    let _ = public;
+    options
+        .open(path)
+        .and_then(|mut f| f.write_all(contents.as_bytes()))
+        .with_context(|| format!("failed to write `{}`", path.display()))
+}
+
+/// Key ids are random in minisign, the start of the public key makes for one
+/// just as unique.
+fn key_id(pk: &PublicKey) -> [u8; 8] {
+    pk[..8].try_into().unwrap()
+}
+
+/// Formats a key id the way minisign prints it, as a little endian number.
+fn hex_id(id: &[u8]) -> String {
+    id.iter().rev().map(|b| format!("{:02X}", b)).collect()
+}
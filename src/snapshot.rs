@@ -0,0 +1,394 @@
+//! Named snapshots of a registry, kept in `.snapshots/NAME` at its root: the
+//! index and `provenance.json` as they were, a manifest of every crate with
+//! its checksum, and hard links to the crate files so that syncs deleting
+//! them later don't lose them. A registry can be put back in the state of
+//! any of its snapshots.
+
+use crate::{
+    clean_index, crate_files, crate_path, index, index_files, is_hidden, link_into_store,
+    provenance, read, replace_file, walk, Error,
+};
+use anyhow::Context as _;
+use cargo::util::errors::*;
+use cargo_util::Sha256;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{self, File};
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Directory at the root of a registry its snapshots are kept in, hidden so
+/// that signing, bundling and cleaning up the registry leave it alone.
+pub const DIR: &str = ".snapshots";
+
+const MANIFEST: &str = "snapshot.json";
+
+#[derive(clap::Args)]
+pub struct SnapshotOptions {
+    #[command(subcommand)]
+    pub command: SnapshotCommand,
+}
+
+#[derive(clap::Subcommand)]
+pub enum SnapshotCommand {
+    /// Record the index and crate files of the registry under a name
+    Create(SnapshotArgs),
+    /// List the snapshots of the registry
+    List(ListArgs),
+    /// Show the crates recorded in a snapshot
+    Show(SnapshotArgs),
+    /// Put the index and crate files of the registry back as a snapshot
+    /// recorded them
+    Restore(RestoreArgs),
+}
+
+#[derive(clap::Args)]
+pub struct SnapshotArgs {
+    /// Path to the local registry, defaults to the configured one
+    #[arg(default_value_t, hide_default_value = true)]
+    pub path: String,
+    /// Name of the snapshot
+    #[arg(default_value_t, hide_default_value = true)]
+    pub name: String,
+}
+
+#[derive(clap::Args)]
+pub struct RestoreArgs {
+    /// Path to the local registry, defaults to the configured one
+    #[arg(default_value_t, hide_default_value = true)]
+    pub path: String,
+    /// Name of the snapshot
+    #[arg(default_value_t, hide_default_value = true)]
+    pub name: String,
+    /// Only print the crates that would be removed and restored
+    #[arg(long, default_value_t = false)]
+    pub dry_run: bool,
+}
+
+#[derive(clap::Args)]
+pub struct ListArgs {
+    /// Path to the local registry, defaults to the configured one
+    #[arg(default_value_t, hide_default_value = true)]
+    pub path: String,
+}
+
+impl SnapshotOptions {
+    pub fn path(&self) -> &str {
+        match self.command {
+            SnapshotCommand::Create(ref args) | SnapshotCommand::Show(ref args) => &args.path,
+            SnapshotCommand::Restore(ref args) => &args.path,
+            SnapshotCommand::List(ref args) => &args.path,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Manifest {
+    name: String,
+    created_at: String,
+    crates: Vec<Crate>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Crate {
+    name: String,
+    version: String,
+    cksum: String,
+    /// Where the crate file is in the registry, relative to its root, or
+    /// nothing when the crate files are hosted elsewhere.
+    file: Option<String>,
+}
+
+pub fn snapshot(options: &SnapshotOptions, store: Option<&str>) -> CargoResult<()> {
+    let root = Path::new(options.path());
+    match options.command {
+        SnapshotCommand::Create(ref args) => create(root, &args.name),
+        SnapshotCommand::List(_) => list(root),
+        SnapshotCommand::Show(ref args) => show(root, &args.name),
+        SnapshotCommand::Restore(ref args) => restore(args, store),
+    }
+}
+
+/// Tells whether `name` can name a snapshot: letters, digits, `-`, `_` and
+/// `.`, but not first, so it stays a directory right below `.snapshots`.
+fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+fn create(root: &Path, name: &str) -> CargoResult<()> {
+    if !valid_name(name) {
+        anyhow::bail!(
+            "invalid snapshot name `{}`, only letters, digits, `-`, `_` and `.` are allowed",
+            name
+        );
+    }
+    let index = index::dir(root);
+    if !index.is_dir() {
+        anyhow::bail!("`{}` isn't a local registry", root.display());
+    }
+    let dir = root.join(DIR).join(name);
+    if dir.exists() {
+        anyhow::bail!("snapshot `{}` already exists", name);
+    }
+
+    // Built next to where it goes and renamed at the end, so that a snapshot
+    // that fails halfway isn't mistaken for a complete one.
+    let tmp = root.join(DIR).join(format!(".{}.tmp", name));
+    if tmp.exists() {
+        fs::remove_dir_all(&tmp)?;
+    }
+    fs::create_dir_all(tmp.join("crates"))
+        .with_context(|| format!("failed to create `{}`", tmp.display()))?;
+
+    let mut crates = Vec::new();
+    for pkg in index::entries(root)? {
+        let file = crate_path(root, &pkg.name, &pkg.vers);
+        let file = if file.is_file() {
+            link(&file, &tmp.join("crates").join(file.file_name().unwrap()))?;
+            Some(relative(root, &file))
+        } else {
+            None
+        };
+        crates.push(Crate {
+            name: pkg.name,
+            version: pkg.vers,
+            cksum: pkg.cksum,
+            file,
+        });
+    }
+    crates.sort_by(|a, b| (&a.name, &a.version).cmp(&(&b.name, &b.version)));
+
+    let mut files = index_files(&index)?;
+    files.push(index.join("config.json"));
+    for file in files.into_iter().filter(|f| f.is_file()) {
+        let dst = tmp.join("index").join(file.strip_prefix(&index).unwrap());
+        fs::create_dir_all(dst.parent().unwrap())?;
+        fs::copy(&file, &dst)?;
+    }
+    if root.join(provenance::FILE).is_file() {
+        fs::copy(root.join(provenance::FILE), tmp.join(provenance::FILE))?;
+    }
+
+    let manifest = Manifest {
+        name: name.to_string(),
+        created_at: humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+        crates,
+    };
+    let json = serde_json::to_string_pretty(&manifest).unwrap();
+    File::create(tmp.join(MANIFEST)).and_then(|mut f| f.write_all(json.as_bytes()))?;
+    fs::rename(&tmp, &dir)
+        .with_context(|| format!("failed to create snapshot `{}`", dir.display()))?;
+
+    println!(
+        "created snapshot `{}` of {} crates",
+        name,
+        manifest.crates.len()
+    );
+    Ok(())
+}
+
+fn list(root: &Path) -> CargoResult<()> {
+    let mut manifests = Vec::new();
+    if let Ok(entries) = root.join(DIR).read_dir() {
+        for entry in entries {
+            let path = entry?.path();
+            if path.is_dir() && !is_hidden(&path) {
+                manifests.push(load(&path)?);
+            }
+        }
+    }
+    manifests.sort_by(|a, b| (&a.created_at, &a.name).cmp(&(&b.created_at, &b.name)));
+    for manifest in manifests {
+        println!(
+            "{}  {}  {} crates",
+            manifest.name,
+            manifest.created_at,
+            manifest.crates.len()
+        );
+    }
+    Ok(())
+}
+
+fn show(root: &Path, name: &str) -> CargoResult<()> {
+    let manifest = find(root, name)?;
+    println!("{}", manifest.name);
+    println!("  created: {}", manifest.created_at);
+    println!("  crates: {}", manifest.crates.len());
+    for krate in manifest.crates.iter() {
+        println!("  {} {} {}", krate.name, krate.version, krate.cksum);
+    }
+    Ok(())
+}
+
+/// Puts the registry back in the state recorded by a snapshot: its index and
+/// provenance as they were, and exactly the crate files it had then, taken
+/// from the snapshot or, failing that, from `store`. Crate files added since
+/// are removed.
+fn restore(args: &RestoreArgs, store: Option<&str>) -> CargoResult<()> {
+    let root = Path::new(&args.path);
+    let manifest = find(root, &args.name)?;
+    let dir = root.join(DIR).join(&args.name);
+    let current = index::entries(root)?
+        .into_iter()
+        .map(|pkg| ((pkg.name, pkg.vers), pkg.cksum))
+        .collect::<BTreeMap<_, _>>();
+    let recorded = manifest
+        .crates
+        .iter()
+        .map(|krate| ((krate.name.clone(), krate.version.clone()), krate))
+        .collect::<BTreeMap<_, _>>();
+
+    let removed = current
+        .iter()
+        .filter(|(key, cksum)| recorded.get(*key).map(|krate| &krate.cksum) != Some(cksum))
+        .map(|(key, _)| key)
+        .collect::<Vec<_>>();
+    let restored = recorded
+        .iter()
+        .filter(|(key, krate)| {
+            current.get(*key) != Some(&krate.cksum)
+                || krate.file.as_ref().is_some_and(|f| !root.join(f).is_file())
+        })
+        .map(|(_, krate)| *krate)
+        .collect::<Vec<_>>();
+    for (name, version) in removed.iter() {
+        println!("- {} {}", name, version);
+    }
+    for krate in restored.iter() {
+        println!("+ {} {}", krate.name, krate.version);
+    }
+    if args.dry_run {
+        println!(
+            "would remove {} crates and restore {}",
+            removed.len(),
+            restored.len()
+        );
+        return Ok(());
+    }
+
+    // Every crate file is found and checked before anything changes.
+    let mut files = Vec::new();
+    for krate in restored.iter() {
+        if let Some(ref file) = krate.file {
+            files.push((saved_file(&dir, krate, store)?, root.join(file)));
+        }
+    }
+
+    let keep = manifest
+        .crates
+        .iter()
+        .filter_map(|krate| krate.file.as_ref())
+        .map(|file| root.join(file))
+        .collect::<HashSet<_>>();
+    for file in crate_files(root)? {
+        if !keep.contains(&file) {
+            fs::remove_file(&file)?;
+        }
+    }
+    for (src, dst) in files {
+        fs::create_dir_all(dst.parent().unwrap())?;
+        replace_file(&dst)?;
+        link(&src, &dst)?;
+        if let Some(store) = store {
+            link_into_store(&dst, Path::new(store))?;
+        }
+    }
+
+    let index = index::dir(root);
+    clean_index(root, &HashSet::new())?;
+    let saved = dir.join("index");
+    if saved.is_dir() {
+        for file in walk(&saved)? {
+            let dst = index.join(file.strip_prefix(&saved).unwrap());
+            fs::create_dir_all(dst.parent().unwrap())?;
+            fs::copy(&file, &dst)?;
+        }
+    }
+    if dir.join(provenance::FILE).is_file() {
+        fs::copy(dir.join(provenance::FILE), root.join(provenance::FILE))?;
+    }
+    if root.join(provenance::FILE).is_file() {
+        provenance::record(root, Vec::new())?;
+    }
+
+    println!(
+        "restored snapshot `{}`, removing {} crates and restoring {}",
+        args.name,
+        removed.len(),
+        restored.len()
+    );
+    Ok(())
+}
+
+/// Returns the copy of the crate file of `krate` kept by the snapshot in
+/// `dir`, or by `store`, once checked against its checksum.
+fn saved_file(dir: &Path, krate: &Crate, store: Option<&str>) -> CargoResult<PathBuf> {
+    let filename = format!("{}-{}.crate", krate.name, krate.version);
+    let mut candidates = vec![dir.join("crates").join(&filename)];
+    if let (Some(store), Some(shard)) = (store, krate.cksum.get(..2)) {
+        candidates.push(Path::new(store).join(shard).join(&krate.cksum));
+    }
+    let src = match candidates.into_iter().find(|src| src.is_file()) {
+        Some(src) => src,
+        None => anyhow::bail!(
+            "`{}` is missing from the snapshot{}",
+            filename,
+            if store.is_some() {
+                " and the store"
+            } else {
+                ""
+            }
+        ),
+    };
+    let mut sha = Sha256::new();
+    let actual = sha.update_path(&src)?.finish_hex();
+    if actual != krate.cksum {
+        return Err(Error::Integrity(format!(
+            "checksum mismatch for `{}`: expected {}, found {}",
+            src.display(),
+            krate.cksum,
+            actual
+        ))
+        .into());
+    }
+    Ok(src)
+}
+
+/// Reads the manifest of the snapshot `name` of the registry at `root`.
+fn find(root: &Path, name: &str) -> CargoResult<Manifest> {
+    let dir = root.join(DIR).join(name);
+    if !valid_name(name) || !dir.join(MANIFEST).is_file() {
+        anyhow::bail!("no snapshot `{}` in `{}`", name, root.display());
+    }
+    load(&dir)
+}
+
+fn load(dir: &Path) -> CargoResult<Manifest> {
+    let path = dir.join(MANIFEST);
+    serde_json::from_str(&read(&path)?).with_context(|| format!("invalid `{}`", path.display()))
+}
+
+/// Hard links `src` to `dst`, which shares the copy in the store of
+/// registries that have one, or copies it where hard links aren't possible.
+fn link(src: &Path, dst: &Path) -> CargoResult<()> {
+    fs::hard_link(src, dst)
+        .or_else(|_| fs::copy(src, dst).map(drop))
+        .with_context(|| format!("failed to copy `{}` to `{}`", src.display(), dst.display()))?;
+    Ok(())
+}
+
+/// Returns `path` relative to `root`, with `/` as separator whatever the
+/// platform, as it is written to the manifest.
+fn relative(root: &Path, path: &Path) -> String {
+    path.strip_prefix(root)
+        .unwrap()
+        .iter()
+        .map(|c| c.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
@@ -0,0 +1,136 @@
+//! The report printed at the end of a sync or add, of what it changed in the
+//! registry and how long each of its phases took.
+
+use crate::message::{self, MessageFormat};
+use crate::progress::{Event, Progress};
+use cargo::util::errors::*;
+use cargo::util::GlobalContext;
+use indicatif::HumanBytes;
+use serde_json::json;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Counts what a sync or add does from its progress events, passing them on
+/// to `inner`.
+pub struct Summary {
+    inner: Option<Arc<dyn Progress>>,
+    state: Mutex<State>,
+}
+
+struct State {
+    start: Instant,
+    downloading: Option<Instant>,
+    writing: Option<Instant>,
+    written: Option<Instant>,
+    copied: u64,
+    packaged: u64,
+    bytes: u64,
+    deleted: u64,
+}
+
+impl Summary {
+    pub fn new(inner: Option<Arc<dyn Progress>>) -> Summary {
+        Summary {
+            inner,
+            state: Mutex::new(State {
+                start: Instant::now(),
+                downloading: None,
+                writing: None,
+                written: None,
+                copied: 0,
+                packaged: 0,
+                bytes: 0,
+                deleted: 0,
+            }),
+        }
+    }
+
+    /// Prints the summary, as status lines or as a JSON message.
+    pub fn print(&self, format: MessageFormat, config: &GlobalContext) -> CargoResult<()> {
+        let state = self.state.lock().unwrap();
+        let end = Instant::now();
+        let download = state.downloading.unwrap_or(end);
+        let write = state.writing.unwrap_or(end).max(download);
+        let clean_up = state.written.unwrap_or(end).max(write);
+        let phases = [
+            ("resolve", download - state.start),
+            ("download", write - download),
+            ("write", clean_up - write),
+            ("clean up", end - clean_up),
+        ];
+
+        if format == MessageFormat::Json {
+            let phases = phases
+                .iter()
+                .map(|(name, time)| (name.to_string(), json!(time.as_secs_f64())))
+                .collect::<serde_json::Map<_, _>>();
+            message::emit(json!({
+                "reason": "summary",
+                "copied": state.copied,
+                "packaged": state.packaged,
+                "bytes": state.bytes,
+                "deleted": state.deleted,
+                "seconds": phases,
+            }));
+            return Ok(());
+        }
+
+        let mut shell = config.shell();
+        shell.status(
+            "Summary",
+            format!(
+                "{} crates copied, {} packaged from git, {} written, {} deleted",
+                state.copied,
+                state.packaged,
+                HumanBytes(state.bytes),
+                state.deleted
+            ),
+        )?;
+        let phases = phases
+            .iter()
+            .map(|(name, time)| format!("{} {}", name, seconds(*time)))
+            .collect::<Vec<_>>();
+        shell.status(
+            "Finished",
+            format!("in {} ({})", seconds(end - state.start), phases.join(", ")),
+        )?;
+        Ok(())
+    }
+}
+
+fn seconds(time: Duration) -> String {
+    format!("{:.1}s", time.as_secs_f64())
+}
+
+impl Progress for Summary {
+    fn report(&self, event: &Event<'_>) {
+        {
+            let mut state = self.state.lock().unwrap();
+            let now = Instant::now();
+            match *event {
+                Event::Downloading { .. } => {
+                    state.downloading.get_or_insert(now);
+                }
+                Event::Writing { .. } => {
+                    state.writing.get_or_insert(now);
+                }
+                Event::Written {
+                    bytes, packaged, ..
+                } => {
+                    state.written = Some(now);
+                    state.bytes += bytes;
+                    if packaged {
+                        state.packaged += 1;
+                    } else {
+                        state.copied += 1;
+                    }
+                }
+                Event::Deleted { .. } => state.deleted += 1,
+                Event::IndexUpdated { .. } => {}
+            }
+        }
+        if let Some(ref inner) = self.inner {
+            inner.report(event);
+        }
+    }
+}
@@ -1,10 +1,13 @@
+// The oldest tests are kept as they were written, before clippy.
+#![allow(clippy::needless_return, clippy::zero_ptr, clippy::needless_borrows_for_generic_args)]
+
 extern crate tempfile;
 
 use std::env;
 use std::fs::{self, File};
 use std::io::prelude::*;
 use std::process::Command;
-use std::sync::{Once, Mutex, MutexGuard};
+use std::sync::{Arc, Once, Mutex, MutexGuard};
 
 use tempfile::TempDir;
 
@@ -145,6 +148,62 @@ source = "registry+https://github.com/rust-lang/crates.io-index"
     assert!(contents.contains("0.2.6"));
 }
 
+#[test]
+fn locked() {
+    let _l = lock();
+    let td = TempDir::new().unwrap();
+    let lock = td.path().join("Cargo.lock");
+    let registry = td.path().join("registry");
+    fs::create_dir(td.path().join("src")).unwrap();
+    File::create(td.path().join("Cargo.toml")).unwrap().write_all(br#"
+        [package]
+        name = "foo"
+        version = "0.1.0"
+        authors = []
+
+        [dependencies]
+        libc = "0.2.6"
+    "#).unwrap();
+    File::create(td.path().join("src/lib.rs")).unwrap().write_all(b"").unwrap();
+    File::create(&lock).unwrap().write_all(br#"
+[[package]]
+name = "foo"
+version = "0.1.0"
+dependencies = [
+ "libc 0.2.7 (registry+https://github.com/rust-lang/crates.io-index)",
+]
+
+[[package]]
+name = "libc"
+version = "0.2.7"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+"#).unwrap();
+    // Cargo rewrites the lock file in its current format, after which it is
+    // up to date.
+    let err = run_err(cmd().arg(&registry).arg("--sync").arg(&lock).arg("--locked"));
+    assert!(err.contains("needs to be updated but --locked was passed"), "{}", err);
+    run(cmd().arg(&registry).arg("--sync").arg(&lock));
+    run(cmd().arg(&registry).arg("--sync").arg(&lock).arg("--locked"));
+    assert!(registry.join("libc-0.2.7.crate").is_file());
+    let contents = fs::read(&lock).unwrap();
+
+    // The lock file no longer matches the manifest.
+    File::create(td.path().join("Cargo.toml")).unwrap().write_all(br#"
+        [package]
+        name = "foo"
+        version = "0.1.0"
+        authors = []
+
+        [dependencies]
+        libc = "0.2.6"
+        lazycell = "1"
+    "#).unwrap();
+    let err = run_err(cmd().arg(&registry).arg("--sync").arg(&lock).arg("--locked"));
+    assert!(err.contains("needs to be updated but --locked was passed"), "{}", err);
+    assert_eq!(fs::read(&lock).unwrap(), contents);
+    assert!(!registry.join("lazycell-1.3.0.crate").exists());
+}
+
 #[test]
 fn git_dependency() {
     let _l = lock();
@@ -501,12 +560,1742 @@ source = "registry+https://github.com/rust-lang/crates.io-index"
     assert_eq!(contents, r#"{"name":"lazycell","vers":"1.2.1","deps":[{"name":"clippy","req":"^0.0","features":[],"optional":true,"default_features":true,"target":null,"kind":null,"package":null}],"cksum":"b294d6fa9ee409a054354afc4352b0b9ef7ca222c69b8812cbea9e7d2bf3783f","features":{"clippy":["dep:clippy"],"nightly":[],"nightly-testing":["clippy","nightly"]},"yanked":false}"#);
 }
 
-fn run(cmd: &mut Command) -> String {
-    let output = cmd.env("RUST_BACKTRACE", "1").output().unwrap();
-    if !output.status.success() {
-        panic!("failed to run {:?}\n--- stdout\n{}\n--- stderr\n{}", cmd,
-               String::from_utf8_lossy(&output.stdout),
-               String::from_utf8_lossy(&output.stderr));
+/// Serves the local registry at `path` as a sparse registry, returning its URL.
+fn serve_sparse(path: std::path::PathBuf) -> String {
+    use std::io::BufReader;
+    use std::net::TcpListener;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let config = format!(r#"{{"dl":"{}/{{crate}}-{{version}}.crate"}}"#, url);
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut request = String::new();
+            BufReader::new(&stream).read_line(&mut request).unwrap();
+            let file = request.split_whitespace().nth(1).unwrap_or("/")
+                              .trim_start_matches('/').to_string();
+            let body = match file.as_str() {
+                "config.json" => Some(config.clone().into_bytes()),
+                _ if file.ends_with(".crate") => fs::read(path.join(&file)).ok(),
+                _ => fs::read(path.join("index").join(&file)).ok(),
+            };
+            let (status, body) = match body {
+                Some(body) => ("200 OK", body),
+                None => ("404 Not Found", Vec::new()),
+            };
+            let _ = write!(stream, "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                           status, body.len());
+            let _ = stream.write_all(&body);
+        }
+    });
+    url
+}
+
+#[test]
+fn split_sources() {
+    let td = TempDir::new().unwrap();
+    let lock = td.path().join("Cargo.lock");
+    let registry = td.path().join("registry");
+    let alt = td.path().join("alt");
+    run(cmd().arg("add").arg(&alt).arg("lazycell@=1.2.1"));
+
+    let url = serve_sparse(alt);
+
+    fs::create_dir_all(td.path().join(".cargo")).unwrap();
+    fs::write(td.path().join(".cargo/config.toml"),
+              format!("[registries.alt]\nindex = \"sparse+{}/\"\n", url)).unwrap();
+    fs::create_dir(td.path().join("src")).unwrap();
+    File::create(td.path().join("Cargo.toml")).unwrap().write_all(br#"
+        [package]
+        name = "foo"
+        version = "0.1.0"
+        authors = []
+
+        [dependencies]
+        libc = "0.2.6"
+        lazycell = { version = "1.2.1", registry = "alt" }
+    "#).unwrap();
+    File::create(td.path().join("src/lib.rs")).unwrap().write_all(b"").unwrap();
+    File::create(&lock).unwrap().write_all(format!(r#"
+[[package]]
+name = "foo"
+version = "0.1.0"
+dependencies = [
+ "lazycell 1.2.1 (sparse+{0}/)",
+ "libc 0.2.7 (registry+https://github.com/rust-lang/crates.io-index)",
+]
+
+[[package]]
+name = "lazycell"
+version = "1.2.1"
+source = "sparse+{0}/"
+
+[[package]]
+name = "libc"
+version = "0.2.7"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+"#, url).as_bytes()).unwrap();
+
+    let out = run(cmd().current_dir(td.path()).arg(&registry).arg("--sync").arg(&lock)
+                  .arg("--split-sources"));
+    assert!(registry.join("libc-0.2.7.crate").is_file());
+    assert!(!registry.join("lazycell-1.2.1.crate").exists());
+    let split = fs::read_dir(&registry).unwrap()
+        .map(|e| e.unwrap().path())
+        .find(|p| p.file_name().unwrap().to_str().unwrap().starts_with("127.0.0.1-"))
+        .unwrap();
+    assert!(split.join("lazycell-1.2.1.crate").is_file());
+    assert!(split.join("index/la/zy/lazycell").is_file());
+    assert!(!split.join("libc-0.2.7.crate").exists());
+
+    assert!(out.contains("[source.crates-io]\n"), "{}", out);
+    assert!(out.contains("    replace-with = 'local-registry'\n"), "{}", out);
+    assert!(out.contains(&format!("[source.alt]\n    registry = 'sparse+{}/'\n    \
+                                   replace-with = 'local-registry-alt'\n", url)), "{}", out);
+    assert!(out.contains(&format!("[source.local-registry]\n    local-registry = '{}'\n",
+                                  registry.display())), "{}", out);
+    assert!(out.contains(&format!("[source.local-registry-alt]\n    local-registry = '{}'\n",
+                                  split.display())), "{}", out);
+}
+
+#[test]
+fn external_dl() {
+    let td = TempDir::new().unwrap();
+    let lock = td.path().join("Cargo.lock");
+    let registry = td.path().join("registry");
+    let manifest = td.path().join("SHA256SUMS");
+    fs::create_dir(td.path().join("src")).unwrap();
+    File::create(td.path().join("Cargo.toml")).unwrap().write_all(br#"
+        [package]
+        name = "foo"
+        version = "0.1.0"
+        authors = []
+
+        [dependencies]
+        libc = "0.2.6"
+    "#).unwrap();
+    File::create(td.path().join("src/lib.rs")).unwrap().write_all(b"").unwrap();
+    File::create(&lock).unwrap().write_all(br#"
+[[package]]
+name = "foo"
+version = "0.1.0"
+dependencies = [
+ "libc 0.2.7 (registry+https://github.com/rust-lang/crates.io-index)",
+]
+
+[[package]]
+name = "libc"
+version = "0.2.7"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+"#).unwrap();
+    File::create(&manifest).unwrap().write_all(b"\
+0000000000000000000000000000000000000000000000000000000000000000  libc-0.2.7.crate
+").unwrap();
+    let err = run_err(cmd().arg(&registry).arg("--sync").arg(&lock)
+                      .arg("--dl").arg("https://cdn.example.com/{crate}/{version}")
+                      .arg("--dl-manifest").arg(&manifest));
+    assert!(err.contains("checksum mismatch"), "{}", err);
+
+    File::create(&manifest).unwrap().write_all(b"\
+4870ef6725dde13394134e587e4ab4eca13cb92e916209a31c851b49131d3c75  libc-0.2.7.crate
+").unwrap();
+    run(cmd().arg(&registry).arg("--sync").arg(&lock)
+        .arg("--dl").arg("https://cdn.example.com/{crate}/{version}")
+        .arg("--dl-manifest").arg(&manifest));
+
+    assert!(registry.join("index/li/bc/libc").is_file());
+    assert!(!registry.join("libc-0.2.7.crate").exists());
+
+    let mut contents = String::new();
+    File::open(registry.join("index/config.json")).unwrap()
+        .read_to_string(&mut contents).unwrap();
+    assert_eq!(contents, r#"{"dl":"https://cdn.example.com/{crate}/{version}"}"#);
+}
+
+#[test]
+fn root_layout() {
+    let td = TempDir::new().unwrap();
+    let lock = td.path().join("Cargo.lock");
+    let registry = td.path().join("registry");
+    let manifest = td.path().join("SHA256SUMS");
+    fs::create_dir(td.path().join("src")).unwrap();
+    File::create(td.path().join("Cargo.toml")).unwrap().write_all(br#"
+        [package]
+        name = "foo"
+        version = "0.1.0"
+        authors = []
+
+        [dependencies]
+        libc = "0.2.6"
+    "#).unwrap();
+    File::create(td.path().join("src/lib.rs")).unwrap().write_all(b"").unwrap();
+    File::create(&manifest).unwrap().write_all(b"\
+4870ef6725dde13394134e587e4ab4eca13cb92e916209a31c851b49131d3c75  libc-0.2.7.crate
+b608bf5e09bb38b075938d5d261682511bae283ef4549cc24fa66b1b8050de7b  libc-0.2.6.crate
+").unwrap();
+    let err = run_err(cmd().arg(&registry).arg("--sync").arg(&lock).arg("--layout").arg("root"));
+    assert!(err.contains("--dl"), "{}", err);
+
+    for vers in ["0.2.7", "0.2.6"] {
+        File::create(&lock).unwrap().write_all(format!(r#"
+[[package]]
+name = "foo"
+version = "0.1.0"
+dependencies = [
+ "libc {0} (registry+https://github.com/rust-lang/crates.io-index)",
+]
+
+[[package]]
+name = "libc"
+version = "{0}"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+"#, vers).as_bytes()).unwrap();
+        // Only a new registry needs to be told about its layout.
+        let mut sync = cmd();
+        sync.arg(&registry).arg("--sync").arg(&lock)
+            .arg("--dl").arg("https://cdn.example.com/{crate}/{version}")
+            .arg("--dl-manifest").arg(&manifest);
+        if vers == "0.2.7" {
+            sync.arg("--layout").arg("root");
+        }
+        run(&mut sync);
     }
-    String::from_utf8_lossy(&output.stdout).into_owned()
+
+    assert!(!registry.join("index").exists());
+    assert!(registry.join("config.json").is_file());
+    assert!(registry.join("provenance.json").is_file());
+    let contents = fs::read_to_string(registry.join("li/bc/libc")).unwrap();
+    assert_eq!(contents.lines().count(), 1);
+    assert!(contents.contains("\"0.2.6\""), "{}", contents);
+    run(cmd().arg("verify").arg(&registry));
+}
+
+#[test]
+fn keep_versions() {
+    let td = TempDir::new().unwrap();
+    let lock = td.path().join("Cargo.lock");
+    let registry = td.path().join("registry");
+    fs::create_dir(td.path().join("src")).unwrap();
+    File::create(td.path().join("Cargo.toml")).unwrap().write_all(br#"
+        [package]
+        name = "foo"
+        version = "0.1.0"
+        authors = []
+
+        [dependencies]
+        libc = "0.2.5"
+    "#).unwrap();
+    File::create(td.path().join("src/lib.rs")).unwrap().write_all(b"").unwrap();
+
+    for vers in ["0.2.7", "0.2.6", "0.2.5"] {
+        File::create(&lock).unwrap().write_all(format!(r#"
+[[package]]
+name = "foo"
+version = "0.1.0"
+dependencies = [
+ "libc {0} (registry+https://github.com/rust-lang/crates.io-index)",
+]
+
+[[package]]
+name = "libc"
+version = "{0}"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+"#, vers).as_bytes()).unwrap();
+        run(cmd().arg(&registry).arg("--sync").arg(&lock).arg("--keep-versions").arg("2"));
+    }
+
+    // The locked version plus the newest of the older ones
+    assert!(registry.join("libc-0.2.5.crate").is_file());
+    assert!(!registry.join("libc-0.2.6.crate").exists());
+    assert!(registry.join("libc-0.2.7.crate").is_file());
+
+    let mut contents = String::new();
+    File::open(registry.join("index/li/bc/libc")).unwrap()
+        .read_to_string(&mut contents).unwrap();
+    assert_eq!(contents.lines().count(), 2);
+    assert!(contents.contains("\"0.2.5\""));
+    assert!(contents.contains("\"0.2.7\""));
+
+    // Crates without an override keep all their versions
+    run(cmd().arg(&registry).arg("--sync").arg(&lock).arg("--keep-versions").arg("foo=1"));
+    assert!(registry.join("libc-0.2.5.crate").is_file());
+    assert!(registry.join("libc-0.2.7.crate").is_file());
+
+    // Per-crate overrides take precedence
+    run(cmd().arg(&registry).arg("--sync").arg(&lock)
+        .arg("--keep-versions").arg("2").arg("--keep-versions").arg("libc=1"));
+    assert!(registry.join("libc-0.2.5.crate").is_file());
+    assert!(!registry.join("libc-0.2.7.crate").exists());
+
+    // Nothing is left of a crate no longer locked, index file included
+    File::create(td.path().join("Cargo.toml")).unwrap().write_all(br#"
+        [package]
+        name = "foo"
+        version = "0.1.0"
+        authors = []
+    "#).unwrap();
+    File::create(&lock).unwrap().write_all(br#"
+[[package]]
+name = "foo"
+version = "0.1.0"
+"#).unwrap();
+    run(cmd().arg(&registry).arg("--sync").arg(&lock).arg("--keep-versions").arg("libc=0"));
+    assert!(!registry.join("libc-0.2.5.crate").exists());
+    assert!(!registry.join("index/li/bc/libc").exists());
+}
+
+#[test]
+fn no_delete_value() {
+    let td = TempDir::new().unwrap();
+    let lock = td.path().join("Cargo.lock");
+    let registry = td.path().join("registry");
+    fs::create_dir(td.path().join("src")).unwrap();
+    File::create(td.path().join("Cargo.toml")).unwrap().write_all(br#"
+        [package]
+        name = "foo"
+        version = "0.1.0"
+        authors = []
+
+        [dependencies]
+        libc = "0.2.6"
+    "#).unwrap();
+    File::create(td.path().join("src/lib.rs")).unwrap().write_all(b"").unwrap();
+
+    for (vers, no_delete) in [("0.2.7", "true"), ("0.2.6", "true"), ("0.2.6", "false")] {
+        File::create(&lock).unwrap().write_all(format!(r#"
+[[package]]
+name = "foo"
+version = "0.1.0"
+dependencies = [
+ "libc {0} (registry+https://github.com/rust-lang/crates.io-index)",
+]
+
+[[package]]
+name = "libc"
+version = "{0}"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+"#, vers).as_bytes()).unwrap();
+        run(cmd().arg(&registry).arg("--sync").arg(&lock)
+            .arg("--no-delete").arg(no_delete).arg("-v").arg("2"));
+        if no_delete == "true" {
+            assert!(registry.join("libc-0.2.7.crate").is_file());
+        }
+    }
+    assert!(registry.join("libc-0.2.6.crate").is_file());
+    assert!(!registry.join("libc-0.2.7.crate").exists());
+}
+
+#[test]
+fn virtual_workspace() {
+    let td = TempDir::new().unwrap();
+    let lock = td.path().join("Cargo.lock");
+    let registry = td.path().join("registry");
+    fs::create_dir_all(td.path().join("foo/src")).unwrap();
+    File::create(td.path().join("Cargo.toml")).unwrap().write_all(br#"
+        [workspace]
+        members = ["foo"]
+
+        [workspace.dependencies]
+        libc = "0.2.6"
+    "#).unwrap();
+    File::create(td.path().join("foo/Cargo.toml")).unwrap().write_all(br#"
+        [package]
+        name = "foo"
+        version = "0.1.0"
+        authors = []
+
+        [dependencies]
+        libc = { workspace = true }
+    "#).unwrap();
+    File::create(td.path().join("foo/src/lib.rs")).unwrap().write_all(b"").unwrap();
+    File::create(&lock).unwrap().write_all(br#"
+[[package]]
+name = "foo"
+version = "0.1.0"
+dependencies = [
+ "libc 0.2.7 (registry+https://github.com/rust-lang/crates.io-index)",
+]
+
+[[package]]
+name = "libc"
+version = "0.2.7"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+"#).unwrap();
+    run(cmd().arg(&registry).arg("--sync").arg(&lock));
+
+    assert!(registry.join("index/li/bc/libc").is_file());
+    assert!(registry.join("libc-0.2.7.crate").is_file());
+    fs::remove_dir_all(&registry).unwrap();
+
+    // Pointing at a member finds the same workspace
+    run(cmd().arg(&registry).arg("--sync").arg(&lock)
+        .arg("--manifest-path").arg(td.path().join("foo/Cargo.toml")));
+
+    assert!(registry.join("index/li/bc/libc").is_file());
+    assert!(registry.join("libc-0.2.7.crate").is_file());
+
+    // A lock file from somewhere else is rejected
+    let other = td.path().join("foo/Cargo.lock");
+    fs::copy(&lock, &other).unwrap();
+    let err = run_err(cmd().arg(&registry).arg("--sync").arg(&other));
+    assert!(err.contains("is not the lock file of the workspace"), "{}", err);
+}
+
+#[test]
+fn add() {
+    let td = TempDir::new().unwrap();
+    let registry = td.path().join("registry");
+    run(cmd().arg("add").arg(&registry).arg("filetime@=0.1.10"));
+
+    assert!(registry.join("filetime-0.1.10.crate").is_file());
+    assert!(registry.join("index/fi/le/filetime").is_file());
+    // Dependencies are pulled in through cargo's resolver
+    assert!(registry.join("index/li/bc/libc").is_file());
+    let libc = registry.read_dir().unwrap()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_name().to_str().unwrap().starts_with("libc-0.2."))
+        .count();
+    assert_eq!(libc, 1);
+
+    // Adding more crates leaves what's already there alone
+    run(cmd().arg("add").arg(&registry).arg("libc@=0.2.7").arg("lazycell@=1.2.1"));
+    assert!(registry.join("filetime-0.1.10.crate").is_file());
+    assert!(registry.join("libc-0.2.7.crate").is_file());
+    assert!(registry.join("lazycell-1.2.1.crate").is_file());
+
+    let mut contents = String::new();
+    File::open(registry.join("index/li/bc/libc")).unwrap()
+        .read_to_string(&mut contents).unwrap();
+    assert_eq!(contents.lines().count(), 2);
+}
+
+#[test]
+fn add_features() {
+    let td = TempDir::new().unwrap();
+    let registry = td.path().join("registry");
+    run(cmd().arg("add").arg(&registry).arg("lazy_static@=1.2.0"));
+    assert!(registry.join("lazy_static-1.2.0.crate").is_file());
+    assert!(!registry.join("index/sp/in/spin").exists());
+
+    // Optional dependencies come along once a feature asks for them
+    run(cmd().arg("add").arg(&registry).arg("lazy_static@=1.2.0")
+        .arg("--features").arg("spin_no_std"));
+    assert!(registry.join("index/sp/in/spin").is_file());
+
+    let err = run_err(cmd().arg("add").arg(&registry).arg("lazy_static").arg("libc")
+                      .arg("--features").arg("spin_no_std"));
+    assert!(err.contains("must be given as CRATE/FEATURE"), "{}", err);
+}
+
+#[test]
+fn add_dev() {
+    let td = TempDir::new().unwrap();
+    let registry = td.path().join("registry");
+    run(cmd().arg("add").arg(&registry).arg("filetime@=0.1.10"));
+    assert!(!registry.join("index/te/mp/tempdir").exists());
+
+    run(cmd().arg("add").arg(&registry).arg("filetime@=0.1.10").arg("--dev"));
+    assert!(registry.join("index/te/mp/tempdir").is_file());
+}
+
+#[test]
+fn add_from_file() {
+    let td = TempDir::new().unwrap();
+    let registry = td.path().join("registry");
+    let list = td.path().join("approved.txt");
+    File::create(&list).unwrap().write_all(b"\
+# approved crates
+libc@=0.2.7
+lazycell@=1.2.1  # used by the build scripts
+
+").unwrap();
+    run(cmd().arg("add").arg(&registry).arg("--from-file").arg(&list));
+    assert!(registry.join("libc-0.2.7.crate").is_file());
+    assert!(registry.join("lazycell-1.2.1.crate").is_file());
+}
+
+#[test]
+fn add_yanked() {
+    let td = TempDir::new().unwrap();
+    let registry = td.path().join("registry");
+    let err = run_err(cmd().arg("add").arg(&registry).arg("phf_shared@=0.13.0"));
+    assert!(
+        err.contains("`phf_shared@=0.13.0` only matches yanked versions: 0.13.0"),
+        "{}",
+        err
+    );
+    run(cmd()
+        .arg("add")
+        .arg(&registry)
+        .arg("phf_shared@=0.13.0")
+        .arg("--allow-yanked"));
+    assert!(registry.join("phf_shared-0.13.0.crate").is_file());
+}
+
+#[test]
+fn add_git() {
+    let td = TempDir::new().unwrap();
+    let registry = td.path().join("registry");
+    let repo = td.path().join("repo");
+    fs::create_dir_all(repo.join("src")).unwrap();
+    File::create(repo.join("Cargo.toml")).unwrap().write_all(br#"
+        [package]
+        name = "foo"
+        version = "0.1.0"
+        authors = []
+    "#).unwrap();
+    File::create(repo.join("src/lib.rs")).unwrap().write_all(b"").unwrap();
+    let git = |args: &[&str]| {
+        run(Command::new("git")
+            .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+            .args(args)
+            .current_dir(&repo))
+    };
+    git(&["init", "-q"]);
+    git(&["add", "."]);
+    git(&["commit", "-q", "-m", "initial"]);
+    let rev = git(&["rev-parse", "HEAD"]);
+
+    let url = url::Url::from_directory_path(&repo).unwrap();
+    run(cmd()
+        .arg("add")
+        .arg(&registry)
+        .arg("foo")
+        .arg("--git")
+        .arg(url.as_str())
+        .arg("--rev")
+        .arg(rev.trim()));
+    assert!(registry.join("foo-0.1.0.crate").is_file());
+    let mut contents = String::new();
+    File::open(registry.join("index/3/f/foo")).unwrap()
+        .read_to_string(&mut contents).unwrap();
+    assert!(contents.contains("\"vers\":\"0.1.0\""), "{}", contents);
+}
+
+#[test]
+fn publish_local() {
+    let td = TempDir::new().unwrap();
+    let registry = td.path().join("registry");
+    let ws = td.path().join("ws");
+    for (name, manifest) in [
+        ("a", r#"
+            [package]
+            name = "a"
+            version = "0.1.0"
+            authors = []
+        "#),
+        ("b", r#"
+            [package]
+            name = "b"
+            version = "0.2.0"
+            authors = []
+
+            [dependencies]
+            a = { path = "../a", version = "0.1.0" }
+        "#),
+        ("c", r#"
+            [package]
+            name = "c"
+            version = "0.1.0"
+            authors = []
+            publish = false
+        "#),
+    ] {
+        fs::create_dir_all(ws.join(name).join("src")).unwrap();
+        File::create(ws.join(name).join("Cargo.toml")).unwrap()
+            .write_all(manifest.as_bytes()).unwrap();
+        File::create(ws.join(name).join("src/lib.rs")).unwrap();
+    }
+    File::create(ws.join("Cargo.toml")).unwrap().write_all(br#"
+        [workspace]
+        members = ["a", "b", "c"]
+    "#).unwrap();
+
+    run(cmd()
+        .arg("publish-local")
+        .arg(&registry)
+        .arg("--manifest-path")
+        .arg(ws.join("Cargo.toml")));
+    assert!(registry.join("a-0.1.0.crate").is_file());
+    assert!(registry.join("b-0.2.0.crate").is_file());
+    assert!(!registry.join("c-0.1.0.crate").exists());
+
+    let mut contents = String::new();
+    File::open(registry.join("index/1/b")).unwrap()
+        .read_to_string(&mut contents).unwrap();
+    assert!(contents.contains("\"name\":\"a\",\"req\":\"^0.1.0\""), "{}", contents);
+    assert!(!contents.contains("\"cksum\":\"\""), "{}", contents);
+}
+
+#[test]
+fn add_dry_run() {
+    let td = TempDir::new().unwrap();
+    let registry = td.path().join("registry");
+    let fresh = td.path().join("fresh");
+    run(cmd().arg("add").arg(&fresh).arg("libc@=0.2.7").arg("--dry-run"));
+    assert!(!fresh.exists());
+
+    run(cmd().arg("add").arg(&registry).arg("libc@=0.2.7"));
+    let out = run(cmd()
+        .arg("add")
+        .arg(&registry)
+        .arg("libc@=0.2.7")
+        .arg("lazycell@=1.2.1")
+        .arg("--dry-run"));
+    assert!(out.contains("libc 0.2.7 (already in the registry)"), "{}", out);
+    assert!(out.contains("lazycell 1.2.1 ("), "{}", out);
+    assert!(out.contains("would add 1 crates"), "{}", out);
+    assert!(!registry.join("lazycell-1.2.1.crate").exists());
+}
+
+#[test]
+fn git_index() {
+    let td = TempDir::new().unwrap();
+    let lock = td.path().join("Cargo.lock");
+    let registry = td.path().join("registry");
+    fs::create_dir(td.path().join("src")).unwrap();
+    File::create(td.path().join("Cargo.toml")).unwrap().write_all(br#"
+        [package]
+        name = "foo"
+        version = "0.1.0"
+        authors = []
+
+        [dependencies]
+        libc = "0.2.6"
+    "#).unwrap();
+    File::create(td.path().join("src/lib.rs")).unwrap().write_all(b"").unwrap();
+    File::create(&lock).unwrap().write_all(br#"
+[[package]]
+name = "foo"
+version = "0.1.0"
+dependencies = [
+ "libc 0.2.7 (registry+https://github.com/rust-lang/crates.io-index)",
+]
+
+[[package]]
+name = "libc"
+version = "0.2.7"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+"#).unwrap();
+    let git_log = || {
+        run(Command::new("git")
+            .arg("log")
+            .arg("--format=%s")
+            .current_dir(registry.join("index")))
+    };
+    for _ in 0..2 {
+        run(cmd()
+            .arg(&registry)
+            .arg("--sync")
+            .arg(&lock)
+            .arg("--index-format")
+            .arg("git"));
+        assert_eq!(git_log().lines().count(), 1);
+    }
+
+    let mut contents = String::new();
+    File::open(registry.join("index/config.json")).unwrap()
+        .read_to_string(&mut contents).unwrap();
+    assert!(contents.contains("{crate}-{version}.crate"), "{}", contents);
+    assert!(contents.contains("\"dl\":\"file://"), "{}", contents);
+    let files = run(Command::new("git")
+        .arg("ls-files")
+        .current_dir(registry.join("index")));
+    assert!(files.contains("li/bc/libc"), "{}", files);
+}
+
+#[test]
+fn export_static() {
+    let td = TempDir::new().unwrap();
+    let registry = td.path().join("registry");
+    let site = td.path().join("site");
+    run(cmd().arg("add").arg(&registry).arg("libc@=0.2.7"));
+    run(cmd()
+        .arg("export")
+        .arg(&registry)
+        .arg(&site)
+        .arg("--format")
+        .arg("static")
+        .arg("--url")
+        .arg("https://example.com/registry/")
+        .arg("--crates-dir")
+        .arg("crates"));
+
+    assert!(site.join("li/bc/libc").is_file());
+    assert!(site.join("crates/libc-0.2.7.crate").is_file());
+    let mut contents = String::new();
+    File::open(site.join("config.json")).unwrap()
+        .read_to_string(&mut contents).unwrap();
+    assert_eq!(
+        contents,
+        r#"{"dl":"https://example.com/registry/crates/{crate}-{version}.crate"}"#
+    );
+}
+
+#[test]
+fn export_vendor_dir() {
+    let td = TempDir::new().unwrap();
+    let registry = td.path().join("registry");
+    let vendor = td.path().join("vendor");
+    run(cmd().arg("add").arg(&registry).arg("libc@=0.2.7"));
+    run(cmd()
+        .arg("export")
+        .arg(&registry)
+        .arg(&vendor)
+        .arg("--format")
+        .arg("vendor-dir"));
+
+    assert!(vendor.join("libc-0.2.7/Cargo.toml").is_file());
+    let mut contents = String::new();
+    File::open(vendor.join("libc-0.2.7/.cargo-checksum.json")).unwrap()
+        .read_to_string(&mut contents).unwrap();
+    assert!(contents.contains("\"Cargo.toml\":"), "{}", contents);
+    assert!(contents.contains("\"package\":\""), "{}", contents);
+}
+
+#[test]
+fn import_vendor_dir() {
+    let td = TempDir::new().unwrap();
+    let registry = td.path().join("registry");
+    let imported = td.path().join("imported");
+    let vendor = td.path().join("vendor");
+    run(cmd().arg("add").arg(&registry).arg("libc@=0.2.7"));
+    run(cmd()
+        .arg("export")
+        .arg(&registry)
+        .arg(&vendor)
+        .arg("--format")
+        .arg("vendor-dir"));
+    run(cmd()
+        .arg("import")
+        .arg(&imported)
+        .arg(&vendor)
+        .arg("--format")
+        .arg("vendor-dir"));
+    assert!(imported.join("libc-0.2.7.crate").is_file());
+    assert!(imported.join("index/li/bc/libc").is_file());
+
+    File::create(vendor.join("libc-0.2.7/Cargo.toml")).unwrap()
+        .write_all(b"tampered").unwrap();
+    let err = run_err(cmd()
+        .arg("import")
+        .arg(&imported)
+        .arg(&vendor)
+        .arg("--format")
+        .arg("vendor-dir"));
+    assert!(err.contains("checksum mismatch for `Cargo.toml`"), "{}", err);
+}
+
+#[test]
+fn export_oci() {
+    let td = TempDir::new().unwrap();
+    let registry = td.path().join("registry");
+    let layout = td.path().join("layout");
+    run(cmd().arg("add").arg(&registry).arg("libc@=0.2.7"));
+    for tag in ["2024-06", "2024-06", "latest"] {
+        run(cmd()
+            .arg("export")
+            .arg(&registry)
+            .arg(&layout)
+            .arg("--format")
+            .arg("oci")
+            .arg("--tag")
+            .arg(tag));
+    }
+
+    assert!(layout.join("oci-layout").is_file());
+    let mut contents = String::new();
+    File::open(layout.join("index.json")).unwrap()
+        .read_to_string(&mut contents).unwrap();
+    assert_eq!(contents.matches("\"org.opencontainers.image.ref.name\"").count(), 2);
+    assert!(contents.contains("\"2024-06\""), "{}", contents);
+
+    let blobs = fs::read_dir(layout.join("blobs/sha256")).unwrap()
+        .map(|e| fs::read(e.unwrap().path()).unwrap())
+        .collect::<Vec<_>>();
+    let manifest = blobs.iter()
+        .map(|b| String::from_utf8_lossy(b))
+        .find(|b| b.contains("\"artifactType\""))
+        .unwrap();
+    assert!(manifest.contains("\"org.opencontainers.image.title\":\"libc-0.2.7.crate\""),
+            "{}", manifest);
+    assert!(manifest.contains("\"org.opencontainers.image.title\":\"index\""), "{}", manifest);
+}
+
+#[test]
+fn import_oci() {
+    let td = TempDir::new().unwrap();
+    let registry = td.path().join("registry");
+    let imported = td.path().join("imported");
+    let layout = td.path().join("layout");
+    run(cmd().arg("add").arg(&registry).arg("libc@=0.2.7"));
+    run(cmd()
+        .arg("export")
+        .arg(&registry)
+        .arg(&layout)
+        .arg("--format")
+        .arg("oci"));
+    run(cmd()
+        .arg("import")
+        .arg(&imported)
+        .arg(&layout)
+        .arg("--format")
+        .arg("oci"));
+    assert_eq!(fs::read(imported.join("libc-0.2.7.crate")).unwrap(),
+               fs::read(registry.join("libc-0.2.7.crate")).unwrap());
+    assert_eq!(fs::read(imported.join("index/li/bc/libc")).unwrap(),
+               fs::read(registry.join("index/li/bc/libc")).unwrap());
+
+    let err = run_err(cmd()
+        .arg("import")
+        .arg(&imported)
+        .arg(&layout)
+        .arg("--format")
+        .arg("oci")
+        .arg("--tag")
+        .arg("nope"));
+    assert!(err.contains("no manifest tagged `nope`"), "{}", err);
+
+    for entry in fs::read_dir(layout.join("blobs/sha256")).unwrap() {
+        let path = entry.unwrap().path();
+        if fs::metadata(&path).unwrap().len() > 1000 {
+            File::create(&path).unwrap().write_all(b"corrupt").unwrap();
+        }
+    }
+    let err = run_err(cmd()
+        .arg("import")
+        .arg(&imported)
+        .arg(&layout)
+        .arg("--format")
+        .arg("oci"));
+    assert!(err.contains("is corrupt"), "{}", err);
+}
+
+#[test]
+fn import_oci_untrusted() {
+    let td = TempDir::new().unwrap();
+    let registry = td.path().join("registry");
+    run(cmd().arg("add").arg(&registry).arg("libc@=0.2.7"));
+    let entry = fs::read_to_string(registry.join("index/li/bc/libc")).unwrap();
+    let cksum = entry.split("\"cksum\":\"").nth(1).unwrap()[..64].to_string();
+
+    let cases = [
+        (entry.replace("\"name\":\"libc\"", "\"name\":\"../../x\""), "invalid crate name `../../x`"),
+        (entry.replace("\"vers\":\"0.2.7\"", "\"vers\":\"../0.2.7\""), "invalid version `../0.2.7`"),
+        (entry.replace(&cksum, &"0".repeat(64)), "checksum mismatch for `libc-0.2.7.crate`"),
+    ];
+    for (i, (tampered, message)) in cases.iter().enumerate() {
+        let layout = td.path().join(format!("layout{}", i));
+        let imported = td.path().join(format!("imported{}", i));
+        fs::write(registry.join("index/li/bc/libc"), tampered).unwrap();
+        run(cmd().arg("export").arg(&registry).arg(&layout).arg("--format").arg("oci"));
+        let err = run_err(cmd().arg("import").arg(&imported).arg(&layout).arg("--format").arg("oci"));
+        assert!(err.contains(message), "{}", err);
+        assert!(!imported.join("index/li/bc/libc").exists());
+        assert!(!imported.join("libc-0.2.7.crate").exists());
+    }
+}
+
+#[test]
+fn shard_crates() {
+    let td = TempDir::new().unwrap();
+    let lock = td.path().join("Cargo.lock");
+    let registry = td.path().join("registry");
+    fs::create_dir(td.path().join("src")).unwrap();
+    File::create(td.path().join("Cargo.toml")).unwrap().write_all(br#"
+        [package]
+        name = "foo"
+        version = "0.1.0"
+        authors = []
+
+        [dependencies]
+        libc = "0.2.6"
+    "#).unwrap();
+    File::create(td.path().join("src/lib.rs")).unwrap().write_all(b"").unwrap();
+    File::create(&lock).unwrap().write_all(br#"
+[[package]]
+name = "foo"
+version = "0.1.0"
+dependencies = [
+ "libc 0.2.7 (registry+https://github.com/rust-lang/crates.io-index)",
+]
+
+[[package]]
+name = "libc"
+version = "0.2.7"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+"#).unwrap();
+    run(cmd().arg(&registry).arg("--sync").arg(&lock).arg("--no-delete"));
+    assert!(registry.join("libc-0.2.7.crate").is_file());
+
+    run(cmd().arg(&registry).arg("--sync").arg(&lock).arg("--no-delete").arg("--shard-crates"));
+    assert!(!registry.join("libc-0.2.7.crate").exists());
+    assert!(registry.join("crates/li/bc/libc-0.2.7.crate").is_file());
+
+    run(cmd().arg("add").arg(&registry).arg("lazycell@=1.2.1"));
+    assert!(registry.join("crates/la/zy/lazycell-1.2.1.crate").is_file());
+
+    run(cmd().arg(&registry).arg("--sync").arg(&lock).arg("--index-format").arg("git"));
+    assert!(registry.join("crates/li/bc/libc-0.2.7.crate").is_file());
+    assert!(!registry.join("crates/la").exists());
+    let mut contents = String::new();
+    File::open(registry.join("index/config.json")).unwrap()
+        .read_to_string(&mut contents).unwrap();
+    assert!(contents.contains("/crates/{lowerprefix}/{crate}-{version}.crate"), "{}", contents);
+}
+
+#[test]
+#[cfg(unix)]
+fn store() {
+    use std::os::unix::fs::MetadataExt;
+
+    let td = TempDir::new().unwrap();
+    let lock = td.path().join("Cargo.lock");
+    let store = td.path().join("store");
+    fs::create_dir(td.path().join("src")).unwrap();
+    File::create(td.path().join("Cargo.toml")).unwrap().write_all(br#"
+        [package]
+        name = "foo"
+        version = "0.1.0"
+        authors = []
+
+        [dependencies]
+        libc = "0.2.6"
+    "#).unwrap();
+    File::create(td.path().join("src/lib.rs")).unwrap().write_all(b"").unwrap();
+    File::create(&lock).unwrap().write_all(br#"
+[[package]]
+name = "foo"
+version = "0.1.0"
+dependencies = [
+ "libc 0.2.7 (registry+https://github.com/rust-lang/crates.io-index)",
+]
+
+[[package]]
+name = "libc"
+version = "0.2.7"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+"#).unwrap();
+    for registry in ["one", "two", "two"] {
+        run(cmd()
+            .arg(td.path().join(registry))
+            .arg("--sync")
+            .arg(&lock)
+            .arg("--store")
+            .arg(&store));
+    }
+
+    let one = fs::metadata(td.path().join("one/libc-0.2.7.crate")).unwrap();
+    let two = fs::metadata(td.path().join("two/libc-0.2.7.crate")).unwrap();
+    assert_eq!(one.ino(), two.ino());
+    assert_eq!(one.nlink(), 3);
+}
+
+#[test]
+fn bundle() {
+    let td = TempDir::new().unwrap();
+    let registry = td.path().join("registry");
+    let unbundled = td.path().join("unbundled");
+    let bundle = td.path().join("registry.tar.gz");
+    run(cmd().arg("add").arg(&registry).arg("libc@=0.2.7").arg("lazycell@=1.2.1"));
+    run(cmd().arg("bundle").arg(&registry).arg(&bundle));
+    run(cmd().arg("unbundle").arg(&unbundled).arg(&bundle));
+    for file in ["libc-0.2.7.crate", "lazycell-1.2.1.crate", "index/li/bc/libc"] {
+        assert_eq!(fs::read(registry.join(file)).unwrap(),
+                   fs::read(unbundled.join(file)).unwrap());
+    }
+
+    // Tamper with one crate file, everything else should still come out.
+    let tampered = td.path().join("tampered.tar.gz");
+    let mut ar = tar::Builder::new(Vec::new());
+    let mut src = tar::Archive::new(flate2::read::GzDecoder::new(File::open(&bundle).unwrap()));
+    for entry in src.entries().unwrap() {
+        let mut entry = entry.unwrap();
+        let mut header = entry.header().clone();
+        let mut contents = Vec::new();
+        entry.read_to_end(&mut contents).unwrap();
+        if entry.path().unwrap().ends_with("libc-0.2.7.crate") {
+            contents[100] ^= 1;
+        }
+        ar.append_data(&mut header, entry.path().unwrap(), &contents[..]).unwrap();
+    }
+    let mut gz = flate2::write::GzEncoder::new(File::create(&tampered).unwrap(),
+                                               flate2::Compression::fast());
+    gz.write_all(&ar.into_inner().unwrap()).unwrap();
+    gz.finish().unwrap();
+
+    let partial = td.path().join("partial");
+    let err = run_err(cmd().arg("unbundle").arg(&partial).arg(&tampered));
+    assert!(err.contains("libc-0.2.7.crate (checksum mismatch)"), "{}", err);
+    assert!(!partial.join("libc-0.2.7.crate").exists());
+    assert!(partial.join("lazycell-1.2.1.crate").is_file());
+}
+
+#[test]
+fn audit() {
+    let td = TempDir::new().unwrap();
+    let registry = td.path().join("registry");
+    let db = td.path().join("advisory-db");
+    run(cmd().arg("add").arg(&registry).arg("libc@=0.2.7").arg("lazycell@=1.2.1"));
+
+    let advisory = |name: &str, id: &str, extra: &str| {
+        fs::create_dir_all(db.join("crates").join(name)).unwrap();
+        File::create(db.join("crates").join(name).join(format!("{}.md", id))).unwrap()
+            .write_all(format!("```toml
+[advisory]
+id = \"{}\"
+package = \"{}\"
+date = \"2020-01-01\"
+{}
+```
+
+# Something about {}
+", id, name, extra, name).as_bytes()).unwrap();
+    };
+    advisory("lazycell", "RUSTSEC-0000-0001", "informational = \"unmaintained\"
+[versions]
+patched = []");
+    advisory("libc", "RUSTSEC-0000-0002", "[versions]
+patched = [\">= 0.2.5\"]");
+
+    let out = run(cmd().arg("audit").arg(&registry).arg("--db").arg(&db).arg("--json"));
+    assert_eq!(out.trim(), r#"[{"crate":"lazycell","version":"1.2.1","id":"RUSTSEC-0000-0001","title":"Something about lazycell","kind":"unmaintained"}]"#);
+    let out = run(cmd().arg("audit").arg(&registry).arg("--db").arg(&db)
+                       .arg("--message-format").arg("json"));
+    let found: serde_json::Value = serde_json::from_str(out.trim()).unwrap();
+    assert_eq!(found["reason"], "advisory");
+    assert_eq!(found["id"], "RUSTSEC-0000-0001");
+
+    advisory("libc", "RUSTSEC-0000-0003", "[versions]
+patched = [\">= 0.2.8\"]");
+    let err = run_err(cmd().arg("audit").arg(&registry).arg("--db").arg(&db));
+    assert!(err.contains("found 1 vulnerability"), "{}", err);
+    let status = cmd().arg("audit").arg(&registry).arg("--db").arg(&db).output().unwrap().status;
+    assert_eq!(status.code(), Some(6));
+}
+
+#[test]
+fn sbom() {
+    let td = TempDir::new().unwrap();
+    let registry = td.path().join("registry");
+    run(cmd().arg("add").arg(&registry).arg("libc@=0.2.7"));
+
+    let out = run(cmd().arg("sbom").arg(&registry).arg("--format").arg("cyclonedx"));
+    let bom: serde_json::Value = serde_json::from_str(&out).unwrap();
+    assert_eq!(bom["bomFormat"], "CycloneDX");
+    let libc = &bom["components"][0];
+    assert_eq!(libc["purl"], "pkg:cargo/libc@0.2.7");
+    assert_eq!(libc["licenses"][0]["expression"], "MIT OR Apache-2.0");
+    assert_eq!(libc["hashes"][0]["content"].as_str().unwrap().len(), 64);
+
+    let spdx = td.path().join("sbom.spdx.json");
+    run(cmd().arg("sbom").arg(&registry).arg("--format").arg("spdx").arg("-o").arg(&spdx));
+    let doc: serde_json::Value = serde_json::from_slice(&fs::read(&spdx).unwrap()).unwrap();
+    assert_eq!(doc["spdxVersion"], "SPDX-2.3");
+    assert_eq!(doc["packages"][0]["SPDXID"], "SPDXRef-Package-libc-0.2.7");
+    assert_eq!(doc["packages"][0]["licenseDeclared"], "MIT OR Apache-2.0");
+
+    // Crates only told apart by characters SPDX IDs can't have get distinct IDs.
+    for name in ["foo-bar", "foo_bar"] {
+        let path = registry.join("index/fo").join(&name[2..4]).join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, format!(r#"{{"name":"{}","vers":"1.0.0","deps":[],"cksum":"{}","features":{{}},"yanked":false}}"#,
+                                 name, "0".repeat(64))).unwrap();
+    }
+    let out = run(cmd().arg("sbom").arg(&registry).arg("--format").arg("spdx"));
+    let doc: serde_json::Value = serde_json::from_str(&out).unwrap();
+    let ids = doc["packages"].as_array().unwrap().iter()
+                             .map(|p| p["SPDXID"].as_str().unwrap())
+                             .collect::<Vec<_>>();
+    assert_eq!(ids, ["SPDXRef-Package-foo-bar-1.0.0", "SPDXRef-Package-foo-bar-1.0.0-2",
+                     "SPDXRef-Package-libc-0.2.7"]);
+}
+
+#[test]
+fn licenses() {
+    let td = TempDir::new().unwrap();
+    let registry = td.path().join("registry");
+    let policy = td.path().join("policy.toml");
+    run(cmd().arg("add").arg(&registry).arg("libc@=0.2.7").arg("lazycell@=1.2.1"));
+
+    let out = run(cmd().arg("licenses").arg(&registry));
+    assert_eq!(out.trim(), "MIT/Apache-2.0 (2): lazycell 1.2.1, libc 0.2.7");
+
+    File::create(&policy).unwrap().write_all(br#"
+        [licenses]
+        allow = ["Apache-2.0", "BSD-3-Clause"]
+        deny = ["MIT"]
+    "#).unwrap();
+    run(cmd().arg("licenses").arg(&registry).arg("--policy").arg(&policy));
+
+    File::create(&policy).unwrap().write_all(br#"
+        [licenses]
+        allow = ["GPL-3.0"]
+    "#).unwrap();
+    let err = run_err(cmd().arg("licenses").arg(&registry).arg("--policy").arg(&policy));
+    assert!(err.contains("libc 0.2.7: license `MIT/Apache-2.0` isn't allowed"), "{}", err);
+
+    let other = td.path().join("other");
+    let err = run_err(cmd()
+        .arg("add")
+        .arg(&other)
+        .arg("libc@=0.2.7")
+        .arg("--policy")
+        .arg(&policy));
+    assert!(err.contains("1 crates are disallowed by the policy"), "{}", err);
+    assert!(!other.join("libc-0.2.7.crate").exists());
+
+    // A crate that can't be read has an unknown license.
+    fs::write(registry.join("lazycell-1.2.1.crate"), b"not a crate").unwrap();
+    let output = cmd().arg("licenses").arg(&registry).output().unwrap();
+    assert!(output.status.success());
+    let out = String::from_utf8_lossy(&output.stdout);
+    assert!(out.contains("unknown (1): lazycell 1.2.1"), "{}", out);
+    let err = String::from_utf8_lossy(&output.stderr);
+    assert!(err.contains("no license information for `lazycell 1.2.1`"), "{}", err);
+    File::create(&policy).unwrap().write_all(br#"
+        [licenses]
+        allow = ["Apache-2.0", "BSD-3-Clause"]
+        deny = ["MIT"]
+    "#).unwrap();
+    let err = run_err(cmd().arg("licenses").arg(&registry).arg("--policy").arg(&policy));
+    assert!(err.contains("lazycell 1.2.1: no SPDX license"), "{}", err);
+}
+
+#[test]
+fn crate_policy() {
+    let td = TempDir::new().unwrap();
+    let registry = td.path().join("registry");
+    let policy = td.path().join("policy.toml");
+
+    File::create(&policy).unwrap().write_all(br#"
+        deny = ["libc@<0.2.8"]
+    "#).unwrap();
+    let err = run_err(cmd().arg("add").arg(&registry).arg("libc@=0.2.7")
+                           .arg("--policy").arg(&policy));
+    assert!(err.contains("libc 0.2.7: denied by `libc@<0.2.8`"), "{}", err);
+    assert!(!registry.join("libc-0.2.7.crate").exists());
+
+    File::create(&policy).unwrap().write_all(br#"
+        allow-only = ["lazycell"]
+    "#).unwrap();
+    let err = run_err(cmd().arg("add").arg(&registry).arg("libc@=0.2.7")
+                           .arg("--policy").arg(&policy));
+    assert!(err.contains("libc 0.2.7: not in `allow-only`"), "{}", err);
+
+    File::create(&policy).unwrap().write_all(br#"
+        allow-only = ["libc@0.2"]
+    "#).unwrap();
+    run(cmd().arg("add").arg(&registry).arg("libc@=0.2.7").arg("--policy").arg(&policy));
+    assert!(registry.join("libc-0.2.7.crate").exists());
+}
+
+#[test]
+fn config_file() {
+    let td = TempDir::new().unwrap();
+    let registry = td.path().join("registry");
+    let user = td.path().join("config");
+    fs::create_dir_all(user.join("cargo-local-registry")).unwrap();
+    fs::create_dir_all(&registry).unwrap();
+    File::create(user.join("cargo-local-registry/config.toml")).unwrap().write_all(br#"
+        max-crate-size = "1KB"
+    "#).unwrap();
+
+    let err = run_err(cmd().arg("add").arg(&registry).arg("libc@=0.2.7")
+                           .env("XDG_CONFIG_HOME", &user));
+    assert!(err.contains("larger than"), "{}", err);
+
+    // The registry's configuration wins over the user's, and the command
+    // line over both.
+    File::create(registry.join(".local-registry.toml")).unwrap().write_all(br#"
+        max-crate-size = "10MB"
+        policy = "policy.toml"
+    "#).unwrap();
+    File::create(registry.join("policy.toml")).unwrap().write_all(br#"
+        deny = ["libc@<0.2.8"]
+    "#).unwrap();
+    let err = run_err(cmd().arg("add").arg(&registry).arg("libc@=0.2.7")
+                           .env("XDG_CONFIG_HOME", &user));
+    assert!(err.contains("libc 0.2.7: denied by `libc@<0.2.8`"), "{}", err);
+
+    let policy = td.path().join("policy.toml");
+    File::create(&policy).unwrap().write_all(b"").unwrap();
+    run(cmd().arg("add").arg(&registry).arg("libc@=0.2.7").arg("--policy").arg(&policy)
+             .env("XDG_CONFIG_HOME", &user));
+    assert!(registry.join("libc-0.2.7.crate").exists());
+
+    File::create(registry.join(".local-registry.toml")).unwrap().write_all(br#"
+        max-crate-size = "big"
+    "#).unwrap();
+    let err = run_err(cmd().arg("add").arg(&registry).arg("libc@=0.2.7"));
+    assert!(err.contains("invalid configuration"), "{}", err);
+
+    // The command line wins even when it gives the default value.
+    File::create(user.join("cargo-local-registry/config.toml")).unwrap().write_all(br#"
+        index-format = "git"
+    "#).unwrap();
+    let lock = td.path().join("Cargo.lock");
+    fs::create_dir(td.path().join("src")).unwrap();
+    File::create(td.path().join("src/lib.rs")).unwrap();
+    File::create(td.path().join("Cargo.toml")).unwrap().write_all(br#"
+        [package]
+        name = "foo"
+        version = "0.1.0"
+        authors = []
+
+        [dependencies]
+        lazycell = "1.2.1"
+    "#).unwrap();
+    File::create(&lock).unwrap().write_all(br#"
+[[package]]
+name = "foo"
+version = "0.1.0"
+dependencies = [
+ "lazycell 1.2.1 (registry+https://github.com/rust-lang/crates.io-index)",
+]
+
+[[package]]
+name = "lazycell"
+version = "1.2.1"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+"#).unwrap();
+    let local = td.path().join("local");
+    run(cmd().arg(&local).arg("--sync").arg(&lock).arg("--index-format").arg("local")
+             .env("XDG_CONFIG_HOME", &user));
+    assert!(local.join("lazycell-1.2.1.crate").exists());
+    assert!(!local.join("index/.git").exists());
+    let git = td.path().join("git");
+    run(cmd().arg(&git).arg("--sync").arg(&lock).env("XDG_CONFIG_HOME", &user));
+    assert!(git.join("index/.git").is_dir());
+}
+
+#[test]
+fn env_vars() {
+    let td = TempDir::new().unwrap();
+    let registry = td.path().join("registry");
+
+    let err = run_err(cmd().arg("add").arg(&registry).arg("libc@=0.2.7")
+                           .env("CARGO_LOCAL_REGISTRY_MAX_CRATE_SIZE", "1KB"));
+    assert!(err.contains("larger than"), "{}", err);
+
+    run(cmd().arg("add").arg(&registry).arg("libc@=0.2.7").arg("--max-crate-size").arg("10MB")
+             .env("CARGO_LOCAL_REGISTRY_MAX_CRATE_SIZE", "1KB"));
+    assert!(registry.join("libc-0.2.7.crate").exists());
+
+    // With a default registry, `add` and `info` take crates right away, and
+    // a registry given anyway wins.
+    run(cmd().arg("add").arg("lazycell@=1.2.1").env("CARGO_LOCAL_REGISTRY_PATH", &registry));
+    assert!(registry.join("lazycell-1.2.1.crate").exists());
+    let out = run(cmd().arg("info").arg("lazycell").env("CARGO_LOCAL_REGISTRY_PATH", &registry));
+    assert!(out.contains("lazycell 1.2.1"), "{}", out);
+    run(cmd().arg("verify").env("CARGO_LOCAL_REGISTRY_PATH", &registry));
+
+    let other = td.path().join("other");
+    run(cmd().arg("add").arg(&other).arg("lazycell@=1.2.1")
+             .env("CARGO_LOCAL_REGISTRY_PATH", &registry));
+    assert!(other.join("lazycell-1.2.1.crate").exists());
+
+    let err = run_err(cmd().arg("verify").env_remove("CARGO_LOCAL_REGISTRY_PATH")
+                           .env("XDG_CONFIG_HOME", td.path()));
+    assert!(err.contains("no registry path given"), "{}", err);
+}
+
+#[test]
+fn message_format_json() {
+    let td = TempDir::new().unwrap();
+    let registry = td.path().join("registry");
+
+    let out = run(cmd().arg("add").arg(&registry).arg("libc@=0.2.7")
+                       .arg("--message-format").arg("json"));
+    let messages = out.lines()
+                      .map(|l| serde_json::from_str::<serde_json::Value>(l).unwrap())
+                      .collect::<Vec<_>>();
+    let reasons = messages.iter().map(|m| m["reason"].as_str().unwrap()).collect::<Vec<_>>();
+    assert_eq!(reasons, ["downloading", "index-updated", "crate-added", "summary", "finished"]);
+    assert_eq!(messages[2]["name"], "libc");
+    assert_eq!(messages[2]["version"], "0.2.7");
+    assert!(messages[1]["path"].as_str().unwrap().ends_with("index/li/bc/libc"));
+    assert_eq!(messages[3]["copied"], 1);
+    assert_eq!(messages[3]["bytes"], messages[2]["bytes"]);
+    assert_eq!(messages[3]["deleted"], 0);
+
+    let out = run(cmd().arg("add").arg(&registry).arg("libc@=0.2.7").arg("lazycell@=1.2.1")
+                       .arg("--dry-run").arg("--message-format").arg("json"));
+    let messages = out.lines()
+                      .map(|l| serde_json::from_str::<serde_json::Value>(l).unwrap())
+                      .collect::<Vec<_>>();
+    let planned = messages.iter().filter(|m| m["reason"] == "crate-planned").collect::<Vec<_>>();
+    assert_eq!(planned.len(), 2, "{}", out);
+    let libc = planned.iter().find(|m| m["name"] == "libc").unwrap();
+    assert_eq!(libc["version"], "0.2.7");
+    assert_eq!(libc["present"], true);
+    let lazycell = planned.iter().find(|m| m["name"] == "lazycell").unwrap();
+    assert_eq!(lazycell["present"], false);
+    assert_eq!(messages.last().unwrap()["reason"], "dry-run");
+    assert_eq!(messages.last().unwrap()["crates"], 1);
+    assert!(!registry.join("lazycell-1.2.1.crate").exists());
+
+    let out = run(cmd().arg("verify").arg(&registry).arg("--message-format").arg("json"));
+    let message = serde_json::from_str::<serde_json::Value>(out.trim()).unwrap();
+    assert_eq!(message["reason"], "verified");
+
+    fs::write(registry.join("index/li/bc/libc"), "{not json").unwrap();
+    let output = cmd().arg("verify").arg(&registry).arg("--message-format").arg("json")
+                      .output().unwrap();
+    assert!(!output.status.success());
+    let out = String::from_utf8(output.stdout).unwrap();
+    let reasons = out.lines()
+                     .map(|l| serde_json::from_str::<serde_json::Value>(l).unwrap())
+                     .map(|m| m["reason"].as_str().unwrap().to_string())
+                     .collect::<Vec<_>>();
+    assert_eq!(reasons, ["verify-failed", "error"]);
+}
+
+#[test]
+fn exit_codes() {
+    let td = TempDir::new().unwrap();
+    let registry = td.path().join("registry");
+    let policy = td.path().join("policy.toml");
+    let code = |cmd: &mut Command| cmd.output().unwrap().status.code();
+
+    assert_eq!(code(cmd().arg("add").arg(&registry).arg("lazycell@=99.0.0")), Some(3));
+
+    File::create(&policy).unwrap().write_all(br#"
+        deny = ["libc"]
+    "#).unwrap();
+    assert_eq!(code(cmd().arg("add").arg(&registry).arg("libc@=0.2.7")
+                         .arg("--policy").arg(&policy)), Some(6));
+
+    run(cmd().arg("add").arg(&registry).arg("libc@=0.2.7"));
+    File::create(&policy).unwrap().write_all(br#"
+        [licenses]
+        allow = ["GPL-3.0"]
+    "#).unwrap();
+    assert_eq!(code(cmd().arg("licenses").arg(&registry).arg("--policy").arg(&policy)), Some(6));
+
+    let mut contents = fs::read(registry.join("libc-0.2.7.crate")).unwrap();
+    contents[100] ^= 1;
+    fs::write(registry.join("libc-0.2.7.crate"), &contents).unwrap();
+    assert_eq!(code(cmd().arg("verify").arg(&registry)), Some(5));
+    assert_eq!(code(cmd().arg("info").arg(&registry).arg("lazycell")), Some(101));
+
+    fs::write(registry.join("index/li/bc/libc"), "{not json").unwrap();
+    assert_eq!(code(cmd().arg("add").arg(&registry).arg("libc@=0.2.7")), Some(7));
+}
+
+#[test]
+fn add_all_matching() {
+    let td = TempDir::new().unwrap();
+    let registry = td.path().join("registry");
+
+    run(cmd().arg("add").arg(&registry).arg("lazycell@~1.2").arg("--latest"));
+    assert!(registry.join("lazycell-1.2.1.crate").exists());
+    assert!(!registry.join("lazycell-1.2.0.crate").exists());
+
+    // Both are semver compatible, so Cargo can't resolve them together.
+    run(cmd().arg("add").arg(&registry).arg("lazycell@~1.2").arg("libc@=0.2.7")
+             .arg("--all-matching"));
+    assert!(registry.join("lazycell-1.2.0.crate").exists());
+    assert!(registry.join("lazycell-1.2.1.crate").exists());
+    assert!(registry.join("libc-0.2.7.crate").exists());
+    assert!(!registry.join("lazycell-1.3.0.crate").exists());
+
+    let err = run_err(cmd().arg("add").arg(&registry).arg("lazycell").arg("--latest")
+                           .arg("--all-matching"));
+    assert!(err.contains("cannot be used with"), "{}", err);
+
+    // Features only go to the rounds with their crate.
+    let other = td.path().join("other");
+    run(cmd().arg("add").arg(&other).arg("lazycell@~1.2").arg("libc@=0.2.30")
+             .arg("--all-matching").arg("--features").arg("libc/use_std"));
+    assert!(other.join("lazycell-1.2.0.crate").exists());
+    assert!(other.join("lazycell-1.2.1.crate").exists());
+    assert!(other.join("libc-0.2.30.crate").exists());
+
+    // Every round is checked before anything is written.
+    let policy = td.path().join("policy.toml");
+    fs::write(&policy, r#"deny = ["lazycell@=1.2.0"]"#).unwrap();
+    let denied = td.path().join("denied");
+    let err = run_err(cmd().arg("add").arg(&denied).arg("lazycell@~1.2").arg("--all-matching")
+                           .arg("--policy").arg(&policy));
+    assert!(err.contains("lazycell 1.2.0: denied"), "{}", err);
+    assert!(!denied.join("lazycell-1.2.1.crate").exists());
+}
+
+#[test]
+fn add_locked() {
+    // There's no lock file to honor, and the registry is left alone.
+    let td = TempDir::new().unwrap();
+    let registry = td.path().join("registry");
+    for flag in ["--locked", "--frozen"] {
+        let err = run_err(cmd().arg("add").arg(&registry).arg("lazycell@=1.2.1").arg(flag));
+        assert!(err.contains("only apply to `--sync`"), "{}", err);
+        assert!(!registry.exists());
+    }
+}
+
+#[test]
+fn add_host() {
+    let td = TempDir::new().unwrap();
+    let alt = td.path().join("alt");
+    let registry = td.path().join("registry");
+    run(cmd().arg("add").arg(&alt).arg("lazycell@=1.2.1"));
+    let url = serve_sparse(alt);
+
+    // Versions are looked up in the host registry, not in crates.io, which
+    // also has 1.2.0.
+    run(cmd().arg("add").arg(&registry).arg("lazycell@~1.2").arg("--all-matching")
+             .env("CARGO_LOCAL_REGISTRY_HOST", format!("sparse+{}/", url)));
+    assert!(registry.join("lazycell-1.2.1.crate").exists());
+    assert!(!registry.join("lazycell-1.2.0.crate").exists());
+}
+
+#[test]
+fn sign() {
+    let td = TempDir::new().unwrap();
+    let registry = td.path().join("registry");
+    let key = td.path().join("registry.key");
+    let other = td.path().join("other.key");
+    run(cmd().arg("keygen").arg(&key));
+    run(cmd().arg("keygen").arg(&other));
+    assert!(td.path().join("registry.key.pub").is_file());
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = |path| fs::metadata(td.path().join(path)).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode("registry.key"), 0o600);
+        assert_eq!(mode("registry.key.pub"), 0o644);
+    }
+    let err = run_err(cmd().arg("keygen").arg(&key));
+    assert!(err.contains("already exists"), "{}", err);
+
+    run(cmd().arg("add").arg(&registry).arg("libc@=0.2.7").arg("--sign-key").arg(&key));
+    let sums = fs::read_to_string(registry.join("SHA256SUMS")).unwrap();
+    assert!(sums.contains("  index/li/bc/libc\n"), "{}", sums);
+    assert!(sums.contains("  libc-0.2.7.crate\n"), "{}", sums);
+    run(cmd().arg("verify").arg(&registry)
+             .arg("--signature").arg(td.path().join("registry.key.pub")));
+
+    let err = run_err(cmd().arg("verify").arg(&registry)
+                           .arg("--signature").arg(td.path().join("other.key.pub")));
+    assert!(err.contains("is signed with key"), "{}", err);
+
+    // The signature survives a bundle.
+    let bundle = td.path().join("registry.tar.gz");
+    let unbundled = td.path().join("unbundled");
+    run(cmd().arg("bundle").arg(&registry).arg(&bundle));
+    run(cmd().arg("unbundle").arg(&unbundled).arg(&bundle));
+    run(cmd().arg("verify").arg(&unbundled)
+             .arg("--signature").arg(td.path().join("registry.key.pub")));
+
+    let mut contents = fs::read(registry.join("libc-0.2.7.crate")).unwrap();
+    contents[100] ^= 1;
+    fs::write(registry.join("libc-0.2.7.crate"), &contents).unwrap();
+    let err = run_err(cmd().arg("verify").arg(&registry));
+    assert!(err.contains("libc-0.2.7.crate (checksum mismatch)"), "{}", err);
+
+    fs::write(registry.join("SHA256SUMS"), sums.replace("libc-0.2.7", "libc-0.2.8")).unwrap();
+    let err = run_err(cmd().arg("verify").arg(&registry)
+                           .arg("--signature").arg(td.path().join("registry.key.pub")));
+    assert!(err.contains("`SHA256SUMS` doesn't match its signature"), "{}", err);
+}
+
+#[test]
+fn sha256_files() {
+    let td = TempDir::new().unwrap();
+    let registry = td.path().join("registry");
+    run(cmd().arg("add").arg(&registry).arg("libc@=0.2.7").arg("--sha256-files"));
+    let sidecar = fs::read_to_string(registry.join("libc-0.2.7.crate.sha256")).unwrap();
+    let index = fs::read_to_string(registry.join("index/li/bc/libc")).unwrap();
+    let (cksum, file) = sidecar.trim_end().split_once("  ").unwrap();
+    assert_eq!(file, "libc-0.2.7.crate");
+    assert!(index.contains(&format!("\"cksum\":\"{}\"", cksum)), "{}", index);
+
+    // Once there, sidecars are kept up to date without the flag.
+    run(cmd().arg("add").arg(&registry).arg("lazycell@=1.2.1"));
+    assert!(registry.join("lazycell-1.2.1.crate.sha256").is_file());
+
+    let export = td.path().join("export");
+    run(cmd().arg("export").arg(&registry).arg(&export)
+             .arg("--format").arg("static").arg("--url").arg("https://example.com"));
+    assert!(export.join("libc-0.2.7.crate.sha256").is_file());
+}
+
+#[test]
+fn max_crate_size() {
+    let td = TempDir::new().unwrap();
+    let registry = td.path().join("registry");
+    let err = run_err(cmd().arg("add").arg(&registry).arg("libc@=0.2.7")
+                           .arg("--max-crate-size").arg("10KB"));
+    assert!(err.contains("1 crates are larger than the maximum of 10000 bytes"), "{}", err);
+    assert!(err.contains("libc 0.2.7 ("), "{}", err);
+    assert!(!registry.join("libc-0.2.7.crate").exists());
+
+    run(cmd().arg("add").arg(&registry).arg("libc@=0.2.7").arg("--max-crate-size").arg("1MiB"));
+    assert!(registry.join("libc-0.2.7.crate").exists());
+
+    let err = run_err(cmd().arg("add").arg(&registry).arg("libc@=0.2.7")
+                           .arg("--max-crate-size").arg("10XB"));
+    assert!(err.contains("invalid size unit in `10XB`"), "{}", err);
+}
+
+#[test]
+fn provenance() {
+    let td = TempDir::new().unwrap();
+    let registry = td.path().join("registry");
+    run(cmd().arg("add").arg(&registry).arg("libc@=0.2.7").env("USER", "operator"));
+
+    let records: serde_json::Value =
+        serde_json::from_slice(&fs::read(registry.join("provenance.json")).unwrap()).unwrap();
+    let libc = &records["libc-0.2.7"];
+    assert_eq!(libc["command"], "add");
+    assert_eq!(libc["added_by"], "operator");
+    assert!(libc["source"].as_str().unwrap().contains("crates.io"), "{}", libc);
+    assert!(libc["tool"].as_str().unwrap().starts_with("cargo-local-registry "), "{}", libc);
+
+    // Adding it again keeps the original record.
+    run(cmd().arg("add").arg(&registry).arg("libc@=0.2.7").env("USER", "someone-else"));
+    let out = run(cmd().arg("info").arg(&registry).arg("libc"));
+    assert!(out.starts_with("libc 0.2.7\n"), "{}", out);
+    assert!(out.contains(" by operator\n"), "{}", out);
+    assert!(out.contains("  via: cargo-local-registry "), "{}", out);
+
+    let out = run(cmd().arg("info").arg(&registry).arg("libc").arg("--message-format").arg("json"));
+    let info: serde_json::Value = serde_json::from_str(out.trim()).unwrap();
+    assert_eq!(info["reason"], "crate-info");
+    assert_eq!(info["version"], "0.2.7");
+    assert_eq!(info["provenance"]["added_by"], "operator");
+
+    let err = run_err(cmd().arg("info").arg(&registry).arg("libc@0.3"));
+    assert!(err.contains("no version of `libc@0.3`"), "{}", err);
+}
+
+#[test]
+fn snapshot() {
+    let td = TempDir::new().unwrap();
+    let registry = td.path().join("registry");
+    run(cmd().arg("add").arg(&registry).arg("libc@=0.2.7").arg("lazycell@=1.2.1"));
+    let out = run(cmd().arg("snapshot").arg("create").arg(&registry).arg("2024-06-release"));
+    assert!(out.contains("created snapshot `2024-06-release` of 2 crates"), "{}", out);
+
+    let snapshot = registry.join(".snapshots/2024-06-release");
+    assert!(snapshot.join("index/li/bc/libc").is_file());
+    assert!(snapshot.join("provenance.json").is_file());
+    fs::remove_file(registry.join("libc-0.2.7.crate")).unwrap();
+    assert!(snapshot.join("crates/libc-0.2.7.crate").is_file());
+    fs::copy(snapshot.join("crates/libc-0.2.7.crate"), registry.join("libc-0.2.7.crate")).unwrap();
+    run(cmd().arg("verify").arg(&registry));
+
+    let err = run_err(cmd().arg("snapshot").arg("create").arg(&registry).arg("2024-06-release"));
+    assert!(err.contains("snapshot `2024-06-release` already exists"), "{}", err);
+    let err = run_err(cmd().arg("snapshot").arg("create").arg(&registry).arg("../up"));
+    assert!(err.contains("invalid snapshot name `../up`"), "{}", err);
+
+    run(cmd().arg("snapshot").arg("create").arg(&registry).arg("later"));
+    let out = run(cmd().arg("snapshot").arg("list").arg(&registry));
+    let names = out.lines().map(|l| l.split_whitespace().next().unwrap()).collect::<Vec<_>>();
+    assert_eq!(names, ["2024-06-release", "later"]);
+
+    let out = run(cmd().arg("snapshot").arg("show").arg(&registry).arg("2024-06-release"));
+    assert!(out.starts_with("2024-06-release\n"), "{}", out);
+    assert!(out.contains("  crates: 2\n"), "{}", out);
+    assert!(out.contains("  lazycell 1.2.1 "), "{}", out);
+    let err = run_err(cmd().arg("snapshot").arg("show").arg(&registry).arg("missing"));
+    assert!(err.contains("no snapshot `missing`"), "{}", err);
+}
+
+#[test]
+fn snapshot_restore() {
+    let td = TempDir::new().unwrap();
+    let registry = td.path().join("registry");
+    run(cmd().arg("add").arg(&registry).arg("libc@=0.2.7").arg("lazycell@=1.2.1"));
+    run(cmd().arg("snapshot").arg("create").arg(&registry).arg("base"));
+    run(cmd().arg("add").arg(&registry).arg("bitflags@=1.3.2"));
+    fs::remove_file(registry.join("lazycell-1.2.1.crate")).unwrap();
+    fs::remove_file(registry.join("index/la/zy/lazycell")).unwrap();
+
+    let out = run(cmd().arg("snapshot").arg("restore").arg(&registry).arg("base").arg("--dry-run"));
+    assert!(out.contains("- bitflags 1.3.2\n"), "{}", out);
+    assert!(out.contains("+ lazycell 1.2.1\n"), "{}", out);
+    assert!(out.contains("would remove 1 crates and restore 1"), "{}", out);
+    assert!(registry.join("bitflags-1.3.2.crate").exists());
+    assert!(!registry.join("lazycell-1.2.1.crate").exists());
+
+    run(cmd().arg("snapshot").arg("restore").arg(&registry).arg("base"));
+    assert!(!registry.join("bitflags-1.3.2.crate").exists());
+    assert!(!registry.join("index/bi").exists());
+    assert!(registry.join("lazycell-1.2.1.crate").exists());
+    assert!(registry.join("index/la/zy/lazycell").exists());
+    let records = fs::read_to_string(registry.join("provenance.json")).unwrap();
+    assert!(!records.contains("bitflags"), "{}", records);
+    run(cmd().arg("verify").arg(&registry));
+
+    // Only snapshots right below `.snapshots` can be restored.
+    fs::create_dir(td.path().join("outside")).unwrap();
+    fs::copy(registry.join(".snapshots/base/snapshot.json"),
+             td.path().join("outside/snapshot.json")).unwrap();
+    let err = run_err(cmd().arg("snapshot").arg("restore").arg(&registry).arg("../../outside"));
+    assert!(err.contains("no snapshot `../../outside`"), "{}", err);
+
+    // Crate files missing from the snapshot are taken from the store.
+    let store = td.path().join("store");
+    run(cmd().arg("add").arg(&registry).arg("bitflags@=1.3.2").arg("--store").arg(&store));
+    run(cmd().arg("snapshot").arg("create").arg(&registry).arg("with-bitflags"));
+    fs::remove_file(registry.join(".snapshots/with-bitflags/crates/bitflags-1.3.2.crate")).unwrap();
+    run(cmd().arg("snapshot").arg("restore").arg(&registry).arg("base"));
+    let err = run_err(cmd().arg("snapshot").arg("restore").arg(&registry).arg("with-bitflags"));
+    assert!(err.contains("`bitflags-1.3.2.crate` is missing from the snapshot"), "{}", err);
+    run(cmd().arg("snapshot").arg("restore").arg(&registry).arg("with-bitflags")
+        .arg("--store").arg(&store));
+    assert!(registry.join("bitflags-1.3.2.crate").exists());
+    run(cmd().arg("verify").arg(&registry));
+}
+
+#[test]
+fn verify_layout() {
+    let td = TempDir::new().unwrap();
+    let registry = td.path().join("registry");
+    run(cmd().arg("add").arg(&registry).arg("libc@=0.2.7").arg("lazycell@=1.2.1"));
+    run(cmd().arg("verify").arg(&registry));
+
+    fs::create_dir_all(registry.join("index/li/ll")).unwrap();
+    fs::rename(registry.join("index/la/zy/lazycell"), registry.join("index/li/ll/lazycell")).unwrap();
+    fs::remove_file(registry.join("libc-0.2.7.crate")).unwrap();
+    fs::write(registry.join("stray-1.0.0.crate"), b"").unwrap();
+    let mut index = fs::OpenOptions::new().append(true)
+        .open(registry.join("index/li/bc/libc")).unwrap();
+    index.write_all(b"\n{not json").unwrap();
+
+    let err = run_err(cmd().arg("verify").arg(&registry));
+    assert!(err.contains("index/li/ll/lazycell:1: `lazycell` belongs in `index/la/zy/lazycell`"),
+            "{}", err);
+    assert!(err.contains("index/li/bc/libc:2: invalid entry"), "{}", err);
+    assert!(err.contains("libc-0.2.7.crate: missing"), "{}", err);
+    assert!(err.contains("stray-1.0.0.crate: not in the index"), "{}", err);
+}
+
+#[test]
+fn library() {
+    use cargo_local_registry::{global_context, index, Error, Registry, SyncOptions};
+
+    let _l = lock();
+    let td = TempDir::new().unwrap();
+    let lock = td.path().join("Cargo.lock");
+    fs::create_dir(td.path().join("src")).unwrap();
+    File::create(td.path().join("Cargo.toml")).unwrap().write_all(br#"
+        [package]
+        name = "foo"
+        version = "0.1.0"
+        authors = []
+
+        [dependencies]
+        libc = "0.2.6"
+    "#).unwrap();
+    File::create(td.path().join("src/lib.rs")).unwrap().write_all(b"").unwrap();
+    File::create(&lock).unwrap().write_all(br#"
+[[package]]
+name = "foo"
+version = "0.1.0"
+dependencies = [
+ "libc 0.2.7 (registry+https://github.com/rust-lang/crates.io-index)",
+]
+
+[[package]]
+name = "libc"
+version = "0.2.7"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+"#).unwrap();
+
+    let config = global_context().unwrap();
+    let registry = Registry::new(td.path().join("registry"));
+    let options = SyncOptions::default().sha256_files(true);
+    let sources = registry.sync(&lock, &options, &config).unwrap();
+    assert_eq!(sources.len(), 1);
+    assert!(registry.path().join("index/li/bc/libc").is_file());
+    assert!(registry.path().join("libc-0.2.7.crate.sha256").is_file());
+
+    let events = Arc::new(Events::default());
+    let options = SyncOptions::default().progress(Some(events.clone()));
+    registry.add(&["lazycell@=1.2.1"], &options, &config).unwrap();
+    assert!(registry.path().join("lazycell-1.2.1.crate").is_file());
+    assert!(registry.path().join("libc-0.2.7.crate").is_file());
+    let size = fs::metadata(registry.path().join("lazycell-1.2.1.crate")).unwrap().len();
+    assert_eq!(*events.0.lock().unwrap(), [
+        "downloading 1".to_string(),
+        "writing lazycell 1.2.1".to_string(),
+        "index lazycell".to_string(),
+        format!("written lazycell 1.2.1 {}", size),
+    ]);
+
+    let err = registry.add(&["lazycell@=99.0.0"], &SyncOptions::default(), &config).unwrap_err();
+    assert!(matches!(err, Error::Resolve(_)), "{:?}", err);
+
+    let path = index::path(registry.path(), "lazycell");
+    // Fields the index types don't know about are kept.
+    let line = fs::read_to_string(&path).unwrap();
+    fs::write(&path, line.trim().replacen('{', r#"{"v":2,"rust_version":"1.60","#, 1)).unwrap();
+    let mut entries = index::read_file(&path).unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].extra["v"], 2);
+    let mut pkg = entries[0].clone();
+    pkg.vers = "1.2.2".to_string();
+    index::merge(&mut entries, pkg);
+    entries[0].yanked = Some(true);
+    index::write_file(&path, &entries).unwrap();
+    let entries = index::read_file(&path).unwrap();
+    let versions = entries.iter().map(|e| (&e.vers[..], e.yanked)).collect::<Vec<_>>();
+    assert_eq!(versions, [("1.2.1", Some(true)), ("1.2.2", Some(false))]);
+    assert!(fs::read_to_string(&path).unwrap().contains(r#""rust_version":"1.60""#));
+    assert_eq!(index::prefix("syn"), "3/s");
+    assert_eq!(index::prefix("serde"), "se/rd");
+    assert_eq!(index::prefix("séré"), "sé/ré");
+
+    fs::write(registry.path().join("index/li/bc/libc"), "{not json").unwrap();
+    let err = registry.add(&["libc@=0.2.7"], &SyncOptions::default(), &config).unwrap_err();
+    match err {
+        Error::CorruptIndex { path, .. } => assert!(path.ends_with("index/li/bc/libc")),
+        err => panic!("{:?}", err),
+    }
+}
+
+#[derive(Default)]
+struct Events(Mutex<Vec<String>>);
+
+impl cargo_local_registry::Progress for Events {
+    fn report(&self, event: &cargo_local_registry::Event<'_>) {
+        use cargo_local_registry::Event;
+        self.0.lock().unwrap().push(match *event {
+            Event::Downloading { crates, .. } => format!("downloading {}", crates),
+            Event::Writing { name, version, .. } => format!("writing {} {}", name, version),
+            Event::Written { name, version, bytes, .. } => {
+                format!("written {} {} {}", name, version, bytes)
+            }
+            Event::IndexUpdated { path, .. } => {
+                format!("index {}", path.file_name().unwrap().to_str().unwrap())
+            }
+            Event::Deleted { path, .. } => format!("deleted {}", path.display()),
+            _ => return,
+        });
+    }
+}
+
+fn run(cmd: &mut Command) -> String {
+    let output = cmd.env("RUST_BACKTRACE", "1").output().unwrap();
+    if !output.status.success() {
+        panic!("failed to run {:?}\n--- stdout\n{}\n--- stderr\n{}", cmd,
+               String::from_utf8_lossy(&output.stdout),
+               String::from_utf8_lossy(&output.stderr));
+    }
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+fn run_err(cmd: &mut Command) -> String {
+    let output = cmd.env("RUST_BACKTRACE", "1").output().unwrap();
+    if output.status.success() {
+        panic!("expected failure running {:?}\n--- stdout\n{}\n--- stderr\n{}", cmd,
+               String::from_utf8_lossy(&output.stdout),
+               String::from_utf8_lossy(&output.stderr));
+    }
+    String::from_utf8_lossy(&output.stderr).into_owned()
 }